@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Language, Parser};
+
+/// Bytes of the source that content sniffing looks at
+const SNIFF_LEN: usize = 64 * 1024;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LanguageArgument {
+    Rust,
+    Json,
+    Unknown,
+}
+
+impl LanguageArgument {
+    pub fn language(self) -> Language {
+        match self {
+            LanguageArgument::Rust => tree_sitter_rust::language(),
+            LanguageArgument::Json => tree_sitter_json::language(),
+            LanguageArgument::Unknown => tree_sitter_structless::language(),
+        }
+    }
+
+    /// The argument that stands for `language`
+    pub fn of(language: Language) -> LanguageArgument {
+        [LanguageArgument::Rust, LanguageArgument::Json]
+            .into_iter()
+            .find(|argument| argument.language() == language)
+            .unwrap_or(LanguageArgument::Unknown)
+    }
+
+    /// The argument after this one, wrapping around after the last
+    pub fn next(self) -> LanguageArgument {
+        let all = LanguageArgument::value_variants();
+        let index = all
+            .iter()
+            .position(|&argument| argument == self)
+            .unwrap_or(0);
+        all[(index + 1) % all.len()]
+    }
+
+    /// Name shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            LanguageArgument::Rust => "rust",
+            LanguageArgument::Json => "json",
+            LanguageArgument::Unknown => "structless",
+        }
+    }
+}
+
+/// Guesses the language of `source`, which was read from `path`, from the
+/// file extension, a shebang or modeline, or the content itself
+pub fn detect(path: Option<&Path>, source: &str) -> LanguageArgument {
+    path.and_then(from_extension)
+        .or_else(|| from_shebang(source))
+        .or_else(|| from_modeline(source))
+        .or_else(|| sniff(source))
+        .unwrap_or(LanguageArgument::Unknown)
+}
+
+fn from_extension(path: &Path) -> Option<LanguageArgument> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "rs" => Some(LanguageArgument::Rust),
+        "json" | "jsonl" | "ndjson" | "geojson" => Some(LanguageArgument::Json),
+        _ => None,
+    }
+}
+
+fn from_name(name: &str) -> Option<LanguageArgument> {
+    match name.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => Some(LanguageArgument::Rust),
+        "json" | "jsonl" => Some(LanguageArgument::Json),
+        _ => None,
+    }
+}
+
+/// Language of a script, like `#!/usr/bin/env rust-script`
+fn from_shebang(source: &str) -> Option<LanguageArgument> {
+    let line = source.lines().next()?;
+    // `#![...]` is an inner attribute of a Rust file, not a shebang
+    let interpreter = line
+        .strip_prefix("#!")
+        .filter(|rest| !rest.starts_with('['))?;
+    interpreter
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .any(|word| word == "rust-script" || word == "run-cargo-script")
+        .then_some(LanguageArgument::Rust)
+}
+
+/// Language named by a vim modeline like `vim: set ft=rust:` or an Emacs
+/// one like `-*- mode: json -*-` in the first or last five lines
+fn from_modeline(source: &str) -> Option<LanguageArgument> {
+    let lines: Vec<_> = source.lines().collect();
+    let first = lines.iter().take(5);
+    let last = lines.iter().skip(5).rev().take(5);
+    first.chain(last).find_map(|line| {
+        if let Some((_, emacs)) = line.split_once("-*-") {
+            let emacs = emacs.split("-*-").next()?;
+            let mode = emacs
+                .split(';')
+                .find_map(|part| part.trim().strip_prefix("mode:"))
+                .unwrap_or(emacs);
+            return from_name(mode.trim());
+        }
+        let (_, vim) = line.split_once("vim:").or_else(|| line.split_once("vi:"))?;
+        vim.split(|c: char| c.is_whitespace() || c == ':')
+            .find_map(|option| {
+                let (key, value) = option.split_once('=')?;
+                matches!(key, "ft" | "filetype" | "syntax").then_some(value)
+            })
+            .and_then(from_name)
+    })
+}
+
+/// Recognizes JSON by a leading `{` or `[` and a start that consists of JSON
+/// tokens only. The grammar reports a start that ends in the middle of a
+/// value with errors, so those are fine here.
+fn sniff(source: &str) -> Option<LanguageArgument> {
+    let trimmed = source.trim_start();
+    if !trimmed.starts_with(['{', '[']) {
+        return None;
+    }
+    let start = if trimmed.len() > SNIFF_LEN {
+        // cut at a line break, not in the middle of a token
+        let mut end = SNIFF_LEN;
+        while !trimmed.is_char_boundary(end) {
+            end -= 1;
+        }
+        let cut = &trimmed[..end];
+        &cut[..cut.rfind('\n').unwrap_or(end)]
+    } else {
+        trimmed
+    };
+
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_json::language()).ok()?;
+    let tree = parser.parse(start, None)?;
+    // an error without children is text that is no JSON token, unless it is
+    // a literal cut off at the end
+    let cut_off = |text: &str| {
+        ["true", "false", "null"]
+            .iter()
+            .any(|literal| literal.starts_with(text))
+    };
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() && node.child_count() == 0 {
+            let text = &start[node.byte_range()];
+            if node.end_byte() < start.len() || !cut_off(text) {
+                return None;
+            }
+        }
+        if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return Some(LanguageArgument::Json);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_by_extension() {
+        let detect_path = |path: &str| detect(Some(Path::new(path)), "");
+        assert_eq!(detect_path("src/main.rs"), LanguageArgument::Rust);
+        assert_eq!(detect_path("logs/service.JSONL"), LanguageArgument::Json);
+        assert_eq!(detect_path("notes.txt"), LanguageArgument::Unknown);
+        assert_eq!(detect_path("Makefile"), LanguageArgument::Unknown);
+    }
+
+    #[test]
+    fn detects_by_shebang_and_modeline() {
+        assert_eq!(
+            detect(None, "#!/usr/bin/env rust-script\nfn main() {}"),
+            LanguageArgument::Rust
+        );
+        assert_eq!(
+            detect(None, "#![allow(unused)]\nfn main() {}"),
+            LanguageArgument::Unknown
+        );
+        assert_eq!(
+            detect(None, "// vim: set ft=rust:\nfn main() {}"),
+            LanguageArgument::Rust
+        );
+        assert_eq!(
+            detect(None, "// -*- mode: rust; indent-tabs-mode: nil -*-\n"),
+            LanguageArgument::Rust
+        );
+        let trailing = format!("{}// vi: filetype=json\n", "x\n".repeat(20));
+        assert_eq!(detect(None, &trailing), LanguageArgument::Json);
+    }
+
+    #[test]
+    fn sniffs_json() {
+        assert_eq!(detect(None, "  {\"a\": [1, 2]}"), LanguageArgument::Json);
+        // cut off in the middle, like the start of a stream
+        assert_eq!(detect(None, "[{\"a\": 1}, {\"b\""), LanguageArgument::Json);
+        assert_eq!(detect(None, "{\"a\": [1, 2"), LanguageArgument::Json);
+        assert_eq!(detect(None, "{\"a\": tr"), LanguageArgument::Json);
+        assert_eq!(
+            detect(None, "{\"a\": 1}\n{\"b\": 2}\n"),
+            LanguageArgument::Json
+        );
+        assert_eq!(
+            detect(None, "[1, 2] trailing text"),
+            LanguageArgument::Unknown
+        );
+        assert_eq!(detect(None, "{ fn main() }"), LanguageArgument::Unknown);
+        assert_eq!(detect(None, "plain text"), LanguageArgument::Unknown);
+    }
+
+    #[test]
+    fn names_languages() {
+        let language = LanguageArgument::of(tree_sitter_json::language());
+        assert_eq!(language, LanguageArgument::Json);
+        assert_eq!(language.name(), "json");
+        assert_eq!(language.next(), LanguageArgument::Unknown);
+        assert_eq!(language.next().next(), LanguageArgument::Rust);
+    }
+}
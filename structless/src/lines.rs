@@ -0,0 +1,369 @@
+use std::{collections::HashSet, ops::Range, sync::Arc};
+
+use tree_sitter::{Node, TreeCursor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Start,
+    End,
+    Whole,
+}
+
+/// A line of the tree view. It does not borrow the tree, so the flattened
+/// lines can be kept between frames; `node` finds the node again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub id: usize,
+    pub range: Range<usize>,
+    /// Number of ancestors of the node
+    pub indent: usize,
+    pub kind: LineKind,
+}
+
+impl Line {
+    fn new(node: Node, indent: usize, kind: LineKind) -> Line {
+        Line {
+            id: node.id(),
+            range: node.byte_range(),
+            indent,
+            kind,
+        }
+    }
+
+    /// Offset in the source the line stands for. Flattened lines are sorted
+    /// by it.
+    fn position(&self) -> usize {
+        match self.kind {
+            LineKind::End => self.range.end,
+            LineKind::Start | LineKind::Whole => self.range.start,
+        }
+    }
+
+    /// Finds the node of this line below `root`, see `path_to`
+    pub fn node<'t>(&self, root: Node<'t>) -> Option<Node<'t>> {
+        path_to(root, self.id, self.range.clone(), self.indent)?.pop()
+    }
+}
+
+/// Walks down from `root` to the node with the given id and range, which is
+/// at most `max_depth` levels below `root`. Returns the nodes on the way,
+/// from `root` to the node itself.
+///
+/// Only the ancestors of the node and their preceding siblings that touch
+/// its start are visited, so this does not depend on the size of the tree.
+fn path_to<'t>(
+    root: Node<'t>,
+    id: usize,
+    range: Range<usize>,
+    max_depth: usize,
+) -> Option<Vec<Node<'t>>> {
+    let Range { start, end } = range;
+    let mut cursor = root.walk();
+    let mut path = vec![root];
+    loop {
+        let node = cursor.node();
+        let contains = node.start_byte() <= start && end <= node.end_byte();
+        if contains && node.id() == id {
+            return Some(path);
+        }
+        // the first child ending at or after `start`; zero width nodes at
+        // `start` may follow a sibling that ends there
+        let descended = contains
+            && path.len() <= max_depth
+            && if start == 0 {
+                cursor.goto_first_child()
+            } else {
+                cursor.goto_first_child_for_byte(start - 1).is_some()
+            };
+        if descended {
+            path.push(cursor.node());
+            continue;
+        }
+        loop {
+            if path.len() == 1 {
+                return None;
+            }
+            if cursor.goto_next_sibling() && cursor.node().start_byte() <= start {
+                *path.last_mut().unwrap() = cursor.node();
+                break;
+            }
+            cursor.goto_parent();
+            path.pop();
+        }
+    }
+}
+
+/// Ancestors of `node` up to `root`, starting with `root`.
+///
+/// `Node::parent` walks down from the root of the tree on every call, so
+/// following it up to the root is quadratic in the depth of `node`.
+pub fn ancestors<'t>(root: Node<'t>, node: Node) -> Vec<Node<'t>> {
+    let mut path = path_to(root, node.id(), node.byte_range(), usize::MAX).unwrap_or_default();
+    path.pop();
+    path
+}
+
+/// All nodes below `node`, children before their parents
+pub fn descendants<'t>(node: &Node<'t>) -> Vec<Node<'t>> {
+    let mut descendants = Vec::new();
+    let mut cursor = node.walk();
+    if !cursor.goto_first_child() {
+        return descendants;
+    }
+    let mut depth = 1;
+    loop {
+        while cursor.goto_first_child() {
+            depth += 1;
+        }
+        loop {
+            descendants.push(cursor.node());
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            cursor.goto_parent();
+            depth -= 1;
+            if depth == 0 {
+                return descendants;
+            }
+        }
+    }
+}
+
+/// Moves `cursor` to the next node after the subtree of the current one,
+/// pushing an `End` line for every expanded node that is left on the way.
+/// Returns `false` once the node `cursor` was created for is left.
+fn skip_subtree(
+    cursor: &mut TreeCursor,
+    depth: &mut usize,
+    indent: usize,
+    lines: &mut Vec<Line>,
+) -> bool {
+    loop {
+        if *depth == 0 {
+            return false;
+        }
+        if cursor.goto_next_sibling() {
+            return true;
+        }
+        cursor.goto_parent();
+        *depth -= 1;
+        lines.push(Line::new(cursor.node(), indent + *depth, LineKind::End));
+    }
+}
+
+/// Lines of `node` and all nodes below it that are not collapsed. Uses a
+/// `TreeCursor` instead of recursion, so that deeply nested input can't
+/// overflow the stack.
+pub fn flatten<F>(node: Node, indent: usize, should_collapse: Arc<F>) -> Vec<Line>
+where
+    F: Fn(usize) -> bool,
+{
+    use LineKind::*;
+    let mut lines = Vec::new();
+    let mut cursor = node.walk();
+    let mut depth = 0;
+    loop {
+        let current = cursor.node();
+        if !should_collapse(current.id()) && cursor.goto_first_child() {
+            lines.push(Line::new(current, indent + depth, Start));
+            depth += 1;
+            continue;
+        }
+        lines.push(Line::new(current, indent + depth, Whole));
+        if !skip_subtree(&mut cursor, &mut depth, indent, &mut lines) {
+            return lines;
+        }
+    }
+}
+
+/// Index of the `Start` or `Whole` line of `node` in `lines`
+pub fn find(lines: &[Line], node: Node) -> Option<usize> {
+    find_at(lines, node.start_byte(), |line| {
+        line.id == node.id() && line.kind != LineKind::End
+    })
+}
+
+/// Index of the `End` line of `node` in `lines`, if it is expanded
+pub fn find_end(lines: &[Line], node: Node) -> Option<usize> {
+    find_at(lines, node.end_byte(), |line| {
+        line.id == node.id() && line.kind == LineKind::End
+    })
+}
+
+/// Binary search for the line at `position` that satisfies `is_line`
+fn find_at(lines: &[Line], position: usize, is_line: impl Fn(&Line) -> bool) -> Option<usize> {
+    let first = lines.partition_point(|line| line.position() < position);
+    lines[first..]
+        .iter()
+        .take_while(|line| line.position() == position)
+        .position(is_line)
+        .map(|offset| first + offset)
+}
+
+/// Flattens `node` again after it was collapsed or expanded, and replaces its
+/// old lines with the new ones. Only the lines of `node` are looked at, so the
+/// cost depends on the size of its subtree, not of the whole tree.
+///
+/// Returns the index of the first line of `node`, and how many lines it had
+/// before and has now. Returns `None` if `node` is not visible.
+pub fn reflatten<F>(
+    lines: &mut Vec<Line>,
+    node: Node,
+    should_collapse: Arc<F>,
+) -> Option<(usize, usize, usize)>
+where
+    F: Fn(usize) -> bool,
+{
+    let start = find(lines, node)?;
+    let end = match lines[start].kind {
+        LineKind::Start => find_at(lines, node.end_byte(), |line| {
+            line.id == node.id() && line.kind == LineKind::End
+        })?,
+        LineKind::End | LineKind::Whole => start,
+    };
+    let new_lines = flatten(node, lines[start].indent, should_collapse);
+    let counts = (end + 1 - start, new_lines.len());
+    lines.splice(start..=end, new_lines);
+    Some((start, counts.0, counts.1))
+}
+
+/// Like `flatten`, but only keeps the nodes spanning one of `matches` and
+/// their ancestors. Ancestors of a match are always expanded, everything else
+/// inside a matching node follows `should_collapse`.
+pub fn flatten_filtered<F>(
+    node: Node,
+    matches: &[Range<usize>],
+    should_collapse: Arc<F>,
+) -> Vec<Line>
+where
+    F: Fn(usize) -> bool,
+{
+    use LineKind::*;
+    let mut matched = HashSet::new();
+    let mut ancestors = HashSet::new();
+    for range in matches {
+        if let Some(descendant) = node.descendant_for_byte_range(range.start, range.end) {
+            matched.insert(descendant.id());
+            // the ancestors of an earlier match are often shared
+            if !ancestors.contains(&descendant.id()) {
+                ancestors.extend(self::ancestors(node, descendant).iter().map(|a| a.id()));
+            }
+        }
+    }
+
+    let should_collapse = Arc::new(|id| !ancestors.contains(&id) && should_collapse(id));
+    let mut lines = Vec::new();
+    let mut cursor = node.walk();
+    let mut depth = 0;
+    loop {
+        let current = cursor.node();
+        if matched.contains(&current.id()) {
+            lines.append(&mut flatten(current, depth, should_collapse.clone()));
+        } else if ancestors.contains(&current.id()) && cursor.goto_first_child() {
+            lines.push(Line::new(current, depth, Start));
+            depth += 1;
+            continue;
+        }
+        if !skip_subtree(&mut cursor, &mut depth, 0, &mut lines) {
+            return lines;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    #[test]
+    fn finds_nodes_of_lines() {
+        // the document and its object share a range, and the empty array
+        // ends where the missing value starts
+        let source = r#"{"a": [], "b": [1, {"c": ""}], "d": }"#;
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_json::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+
+        let lines = flatten(root, 0, Arc::new(|_| false));
+        for line in &lines {
+            assert_eq!(line.node(root).map(|node| node.id()), Some(line.id));
+            let node = line.node(root).unwrap();
+            if line.kind != LineKind::End {
+                assert_eq!(&lines[find(&lines, node).unwrap()], line);
+            }
+        }
+    }
+
+    #[test]
+    fn handles_deep_nesting() {
+        let depth = 100_000;
+        let source = "[".repeat(depth) + &"]".repeat(depth);
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_json::language()).unwrap();
+        let tree = parser.parse(&source, None).unwrap();
+        let root = tree.root_node();
+
+        let lines = flatten(root, 0, Arc::new(|_| false));
+        let deepest = lines.iter().max_by_key(|line| line.indent).unwrap();
+        assert!(deepest.indent >= depth);
+        let node = deepest.node(root).unwrap();
+        assert_eq!(node.id(), deepest.id);
+        assert_eq!(ancestors(root, node).len(), deepest.indent);
+        assert_eq!(
+            descendants(&root).len(),
+            lines.iter().filter(|l| l.kind != LineKind::End).count() - 1
+        );
+
+        let middle = depth / 2..depth / 2 + 1;
+        let filtered = flatten_filtered(root, &[middle], Arc::new(|_| true));
+        assert!(filtered.len() > depth);
+    }
+
+    #[test]
+    fn filters_lines() {
+        let source = r#"[[1, 2], [3, [4]]]"#;
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_json::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+
+        let four = 14..15;
+        let lines = flatten_filtered(root, &[four], Arc::new(|_| true));
+        let shown: Vec<_> = lines
+            .iter()
+            .filter(|line| line.kind != LineKind::End)
+            .map(|line| &source[line.range.clone()])
+            .collect();
+        assert_eq!(shown, vec![source, source, "[3, [4]]", "[4]", "4"]);
+        assert_eq!(lines.len(), 9);
+    }
+
+    #[test]
+    fn reflattens_subtrees() {
+        let source = r#"[[1, [2]], [3]]"#;
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_json::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+        let outer = root.named_child(0).unwrap();
+        let first = outer.named_child(0).unwrap();
+
+        let mut collapsed = HashSet::from([first.id()]);
+        let mut lines = flatten(root, 0, Arc::new(|id| collapsed.contains(&id)));
+        let before = lines.len();
+
+        collapsed.remove(&first.id());
+        let should_collapse = Arc::new(|id| collapsed.contains(&id));
+        let (start, old, new) = reflatten(&mut lines, first, should_collapse.clone()).unwrap();
+        assert_eq!((lines[start].id, old), (first.id(), 1));
+        assert_eq!(lines.len(), before - old + new);
+        assert_eq!(lines, flatten(root, 0, should_collapse));
+
+        collapsed.insert(outer.id());
+        let should_collapse = Arc::new(|id| collapsed.contains(&id));
+        reflatten(&mut lines, outer, should_collapse.clone()).unwrap();
+        assert_eq!(lines, flatten(root, 0, should_collapse.clone()));
+        assert_eq!(reflatten(&mut lines, first, should_collapse), None);
+    }
+}
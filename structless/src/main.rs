@@ -1,38 +1,53 @@
 use std::{
     cmp::{max, min},
     collections::{HashMap, HashSet},
-    convert::Infallible,
-    fmt::Display,
-    fs::File,
-    io::{self, BufRead, BufReader, BufWriter, Bytes, Read, Write},
-    iter::Peekable,
-    path::Path,
-    str::FromStr,
+    fs::{self, File},
+    io::{self, Read},
+    ops,
+    path::{Path, PathBuf},
     sync::Arc,
-    thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
 use clap::StructOpt;
 
+mod language;
+mod lines;
+mod navigation;
+mod parse;
+mod path;
+mod preview;
+mod search;
+mod session;
+mod source_view;
+mod stream;
 mod tree;
 
-use tracing::{error, instrument, trace};
-use tracing_subscriber::fmt::format::Writer;
-use tree_sitter::{Language, Node, Parser, Range, Tree, TreeCursor};
-use tree_sitter_structless;
+use language::LanguageArgument;
+use lines::{flatten, flatten_filtered, Line, LineKind};
+use navigation::Motion;
+use parse::{Fallback, Parsed, Parsing};
+use path::{NodePath, PathSet};
+use preview::Fold;
+use search::{Search, SearchOptions};
+use serde::{Deserialize, Serialize};
+use session::Session;
+use stream::Stream;
+use tracing::{debug, trace};
+use tree::FoldMarker;
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Tree};
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use tui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Alignment, Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    widgets::{Block, Borders, Gauge, Paragraph, Wrap},
     Frame, Terminal,
 };
 
@@ -48,17 +63,23 @@ fn main_interactive() -> anyhow::Result<()> {
     let args = Args::parse();
     println!("args : {:?}", &args);
 
-    let mut reader: BufReader<Box<dyn Read>> = {
-        if args.input == "-" {
-            BufReader::new(Box::new(io::stdin()))
-        } else {
-            let f = File::open(args.input)?;
-            BufReader::new(Box::new(f))
-        }
+    let (source_code, input) = if args.input == "-" {
+        // read stdin in the background, so that the view shows up right away
+        (String::new(), Some(Stream::spawn(io::stdin())))
+    } else {
+        let mut source_code = String::new();
+        File::open(&args.input)?.read_to_string(&mut source_code)?;
+        (source_code, None)
+    };
+    let path = (args.input != "-").then(|| Path::new(&args.input));
+    // the language of stdin is detected once its first text arrived, see
+    // `App::receive_input`
+    let detect = args.language.is_none() && input.is_some();
+    let language = match args.language {
+        Some(language) => language.language(),
+        None if detect => tree_sitter_structless::language(),
+        None => language::detect(path, &source_code).language(),
     };
-
-    let mut source_code = String::new();
-    reader.read_to_string(&mut source_code)?;
 
     // setup terminal
     enable_raw_mode()?;
@@ -67,26 +88,33 @@ fn main_interactive() -> anyhow::Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    terminal.draw(|f| {
-        let size = f.size();
-        let block = Block::default().title("Block").borders(Borders::ALL);
-        f.render_widget(block, size);
-    })?;
+    let timeout = (args.timeout > 0).then(|| Duration::from_secs(args.timeout));
 
-    let language = match args.language {
-        LanguageArgument::Rust => tree_sitter_rust::language(),
-        LanguageArgument::Json => tree_sitter_json::language(),
-        LanguageArgument::Unknown => tree_sitter_structless::language(),
+    // parse in the background, then create app and run it. Nothing of stdin
+    // was read yet, it is checked for errors once it ended.
+    let parsing = Parsing::start(source_code, language, timeout, input.is_none());
+    let result = match wait_for_parse(&mut terminal, parsing) {
+        Ok(Some(parsed)) => {
+            let mut app = App::new(parsed);
+            app.input = input;
+            app.detect = detect;
+            app.timeout = timeout;
+            if let Some(path) = path {
+                let path = path.to_path_buf();
+                app.modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+                app.path = Some(path);
+                app.restore_session(args.language.is_some());
+            }
+            app.auto_reload = args.reload;
+            if args.follow {
+                app.set_follow(true)?;
+            }
+            run_app(&mut terminal, app)
+        }
+        Ok(None) => Ok(()),
+        Err(error) => Err(error),
     };
 
-    // create app and run it
-    let app = App::new(source_code, language)?;
-    let tree = app.tree.clone();
-    let node = tree.root_node();
-    // let tree =.root_node();
-    node.walk();
-    run_app(&mut terminal, app)?;
-
     // restore terminal
     disable_raw_mode()?;
     execute!(
@@ -96,114 +124,1225 @@ fn main_interactive() -> anyhow::Result<()> {
     )?;
     terminal.show_cursor()?;
 
-    Ok(())
+    result
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Shows the progress of `parsing` until it is done. Returns `None` if it was
+/// cancelled with `q` or Ctrl-C.
+fn wait_for_parse<B: Backend>(
+    terminal: &mut Terminal<B>,
+    parsing: Parsing,
+) -> anyhow::Result<Option<Parsed>> {
+    while !parsing.is_finished() {
+        terminal.draw(|f| render_progress(f, &parsing))?;
+        if !event::poll(Duration::from_millis(50))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.code == KeyCode::Char('q') || ctrl_c {
+                parsing.cancel();
+                return Ok(None);
+            }
+        }
+    }
+    parsing.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum ViewMode {
     Tree,
     Text,
+    /// Tree next to a preview of the selected node's source
+    Split,
 }
 
 #[derive(Debug)]
 enum InputMode {
     Normal,
     Editing,
+    /// Entering the node kind to jump to
+    Kind,
+}
+
+/// What the tree of a background parse stands for, see
+/// `App::finish_parsing`
+#[derive(Debug)]
+enum ParseKind {
+    /// The text that arrived on `input` since `parsed_len`, a lot of it
+    Appended,
+    /// The text read from `path` again, parsed with this language. It
+    /// replaces `source_code`.
+    Reloaded(Language),
+    /// All of `source_code`, parsed with another grammar. The paths in
+    /// `session` only fit a tree of `language`, so it is restored afterwards.
+    Language {
+        language: Language,
+        session: Option<Box<Session>>,
+    },
 }
 
+/// Amount of new text that `App::reparse` parses in the background, showing
+/// the progress
+const BACKGROUND_PARSE_LEN: usize = 1024 * 1024;
+
 /// App holds the state of the application
 #[derive(Debug)]
 struct App {
     /// Current value of the input box
     search_term: String,
+    /// Matches of the last search that was run with Enter
+    search: Search,
+    search_options: SearchOptions,
+    /// Error of the last search, shown in the input box
+    search_error: Option<String>,
+    /// Node kind that `]` and `[` jump to
+    kind_term: String,
+    /// All node kinds of the current language, for completing `kind_term`
+    kinds: Vec<&'static str>,
+    /// Candidates of the last completion, shown in the status line
+    kind_candidates: Vec<&'static str>,
     /// Current input mode
     input_mode: InputMode,
+    /// Shown in the title of the tree view, e.g. why a different grammar than
+    /// the requested one is used
+    status: Option<String>,
+    /// Language that was requested, `tree` may be parsed with the structless
+    /// grammar instead
+    language: Language,
+    /// The grammar was picked with `T` or `L`, so it is kept even if most of
+    /// the text are errors
+    language_forced: bool,
+    /// The language is still to be detected from the first text that
+    /// arrives on `input`
+    detect: bool,
     /// History of recorded messages
     tree: Arc<Tree>,
     source_code: String,
+    /// Byte ranges of all lines in `source_code`
+    file_lines: Vec<ops::Range<usize>>,
+    /// File that `source_code` was read from, `None` for stdin
+    path: Option<PathBuf>,
+    /// Modification time of `path` when it was read
+    modified: Option<SystemTime>,
+    /// Reload `path` when it changes
+    auto_reload: bool,
+    /// Next time to check if `path` changed
+    next_check: Instant,
+    /// Why the last reload failed, shown in the title of the tree view
+    reload_error: Option<String>,
+    /// Stream that `source_code` is still being read from
+    input: Option<Stream>,
+    /// Keep reading what is appended to the input and select the newest
+    /// top-level node after every parse, like `tail -f`
+    follow: bool,
+    /// Length and end position of the part of `source_code` that `tree` was
+    /// parsed from
+    parsed_len: usize,
+    parsed_end: Point,
+    /// Earliest time to parse the text that arrived on `input` since the
+    /// last parse
+    next_parse: Instant,
+    /// Parse running in the background, and what its tree stands for.
+    /// `tree` is replaced once it finishes, see `finish_parsing`.
+    parsing: Option<(Parsing, ParseKind)>,
+    /// How long parsing with the requested language may take before falling
+    /// back to the structless grammar
+    timeout: Option<Duration>,
 
-    /// Tree controls
-    collapsed: HashSet<usize>,
+    /// Paths of the expanded nodes, all other nodes are collapsed. Only
+    /// changed through `set_collapsed` and friends, which keep `expanded` in
+    /// sync.
+    folds: PathSet,
+    /// Ids of the nodes at `folds` in the current tree
+    expanded: HashSet<usize>,
+    /// Nodes marked with `m`, by the name of the mark
+    marks: HashMap<char, NodePath>,
+    /// `m` or `'` while waiting for the name of a mark
+    pending_mark: Option<char>,
+    /// Only show nodes matching the last search, together with their ancestors
+    filter: bool,
+    /// Flattened tree, see `visible_lines`. Folding a node only replaces its
+    /// own lines.
+    lines: Vec<Line>,
+    /// Set when `expanded`, `filter` or `search` changed since `lines` was
+    /// computed
+    lines_outdated: bool,
     line_index: usize,
-    list_state: ListState,
+    /// Path of the selected node shown in the title, with the id of the
+    /// node. Only built again when another node is selected or the tree
+    /// changed.
+    title_path: Option<(usize, String)>,
+    tree_state: tree::TreeState,
     view_mode: ViewMode,
+    /// First visible line in `ViewMode::Text`
+    text_scroll: usize,
+    /// Number of characters cut off at the start of each line in
+    /// `ViewMode::Text`
+    text_hscroll: usize,
+    /// Wrap long lines in `ViewMode::Text` instead of chopping them
+    wrap: bool,
+    /// Show the whole file around the selected node in `ViewMode::Text`
+    /// instead of only the node itself
+    context: bool,
+    /// `Direction::Horizontal` puts the panes of `ViewMode::Split` side by
+    /// side, `Direction::Vertical` stacks them
+    split_direction: Direction,
+    /// Share of the tree pane in `ViewMode::Split`, in percent
+    split_ratio: u16,
+    /// Height of the tree or text area in rows, updated on every draw
+    page_height: usize,
+    /// Width of the tree or text area in columns, updated on every draw
+    page_width: usize,
+    /// Pending count prefix, e.g. the `25` in `25s`
+    count: Option<usize>,
+    /// Show all keys in the help line, not only how to get there
+    help: bool,
 }
 
 impl App {
-    pub(crate) fn new(source_code: String, language: Language) -> anyhow::Result<App> {
-        let mut parser = Parser::new();
-        parser.set_language(language)?;
-
-        let tree = Arc::new(parser.parse(&source_code, None).unwrap());
-        // let cursor = tree.root_node().id();
-        Ok(App {
+    pub(crate) fn new(parsed: Parsed) -> App {
+        let Parsed {
+            source: source_code,
+            tree,
+            language,
+            fallback,
+        } = parsed;
+        let kinds = navigation::node_kinds(tree.language());
+        let tree = Arc::new(tree);
+        let file_lines = source_view::line_ranges(&source_code, 0..source_code.len());
+        let parsed_len = source_code.len();
+        let parsed_end = stream::advance(Point::new(0, 0), &source_code);
+        App {
             search_term: "".to_string(),
+            search: Search::default(),
+            search_options: SearchOptions::default(),
+            search_error: None,
+            kind_term: "".to_string(),
+            kinds,
+            kind_candidates: Vec::new(),
             input_mode: InputMode::Normal,
+            status: fallback.map(|fallback| fallback.to_string()),
+            language,
+            language_forced: false,
+            detect: false,
             tree,
             source_code,
-            collapsed: HashSet::new(),
+            file_lines,
+            path: None,
+            modified: None,
+            auto_reload: false,
+            next_check: Instant::now(),
+            reload_error: None,
+            input: None,
+            follow: false,
+            parsed_len,
+            parsed_end,
+            next_parse: Instant::now(),
+            parsing: None,
+            timeout: None,
+            folds: PathSet::default(),
+            expanded: HashSet::new(),
+            marks: HashMap::new(),
+            pending_mark: None,
+            filter: false,
+            lines: Vec::new(),
+            lines_outdated: true,
             line_index: 0,
-            list_state: ListState::default(),
+            title_path: None,
+            tree_state: tree::TreeState::default(),
             view_mode: ViewMode::Tree,
-        })
+            text_scroll: 0,
+            text_hscroll: 0,
+            wrap: false,
+            context: false,
+            split_direction: Direction::Horizontal,
+            split_ratio: 50,
+            page_height: 1,
+            page_width: 1,
+            count: None,
+            help: false,
+        }
+    }
+
+    /// Selects the smallest node that spans `range`, expanding all of its
+    /// ancestors so that it becomes visible
+    fn reveal(&mut self, range: ops::Range<usize>) {
+        let tree = self.tree.clone();
+        if let Some(node) = tree
+            .root_node()
+            .descendant_for_byte_range(range.start, range.end)
+        {
+            self.select_node(node);
+        }
+    }
+
+    /// Selects `node`, expanding all of its ancestors so that it becomes
+    /// visible. Leaves the filter view if the node is filtered out.
+    fn select_node(&mut self, node: Node) {
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        self.folds.insert_ancestors(&NodePath::of(root, node));
+        // innermost first, so that every line is flattened only once
+        for ancestor in lines::ancestors(root, node).into_iter().rev() {
+            if self.expanded.insert(ancestor.id()) {
+                self.update_lines(ancestor);
+            }
+        }
+
+        let position = |app: &mut App| lines::find(app.visible_lines(), node);
+        let mut index = position(self);
+        if index.is_none() && self.is_filtered() {
+            self.filter = false;
+            self.lines_outdated = true;
+            index = position(self);
+        }
+        if let Some(index) = index {
+            self.line_index = index;
+        }
+        self.scroll_to_selection();
+    }
+
+    /// Lines shown in `ViewMode::Text` for the selected `node`, the row of
+    /// the first line in the file, and the byte range that the lines are cut
+    /// to
+    fn text_lines(&self, node: Node) -> (&[ops::Range<usize>], usize, ops::Range<usize>) {
+        if self.context {
+            return (&self.file_lines, 0, 0..self.source_code.len());
+        }
+        let range = node.byte_range();
+        let first = source_view::line_of_byte(&self.file_lines, range.start);
+        let mut last = source_view::line_of_byte(&self.file_lines, range.end);
+        // a node that ends with a line break doesn't show the line after it
+        if last > first && self.file_lines[last].start == range.end {
+            last -= 1;
+        }
+        (&self.file_lines[first..=last], first, range)
+    }
+
+    /// Resets the scroll position of `ViewMode::Text` to the start of the
+    /// selected node
+    fn scroll_to_selection(&mut self) {
+        let tree = self.tree.clone();
+        self.text_hscroll = 0;
+        self.text_scroll = match self.selected_node(tree.root_node()) {
+            // leave some lines of context above the node
+            Some(node) if self.context => node
+                .start_position()
+                .row
+                .saturating_sub(self.page_height / 4),
+            _ => 0,
+        };
+    }
+
+    /// Moves the selection in `ViewMode::Tree`, or scrolls in
+    /// `ViewMode::Text`, to `target`. `line_count` is the number of lines of
+    /// the current view.
+    fn move_to(&mut self, target: usize, line_count: usize) {
+        let target = min(target, line_count.saturating_sub(1));
+        match self.view_mode {
+            ViewMode::Tree | ViewMode::Split => self.line_index = target,
+            ViewMode::Text => self.text_scroll = target,
+        }
+    }
+
+    /// Like `move_to`, but relative to the current position
+    fn move_by(&mut self, delta: isize, line_count: usize) {
+        let current = match self.view_mode {
+            ViewMode::Tree | ViewMode::Split => self.line_index,
+            ViewMode::Text => self.text_scroll,
+        };
+        self.move_to(current.saturating_add_signed(delta), line_count);
+    }
+
+    /// Makes the search match at `range` visible. The tree view selects the
+    /// node of the match. The text view keeps showing the selected node if it
+    /// contains the match, and scrolls to the match.
+    fn show_match(&mut self, range: ops::Range<usize>) {
+        let tree = self.tree.clone();
+        let line_index = self.line_index;
+        let selected = self
+            .visible_lines()
+            .get(line_index)
+            .map(|line| line.range.clone());
+        let contains_match = selected
+            .as_ref()
+            .is_some_and(|node| node.start <= range.start && range.end <= node.end);
+        if self.view_mode != ViewMode::Text || !contains_match {
+            self.reveal(range.clone());
+        }
+
+        if self.view_mode == ViewMode::Text {
+            let node = match self.selected_node(tree.root_node()) {
+                Some(node) => node,
+                None => return,
+            };
+            let (lines, _, bounds) = self.text_lines(node);
+            let line = source_view::line_of_byte(lines, range.start);
+            let line_start = lines[line].start.max(bounds.start);
+            // a match may start in the line break at the end of the line
+            let start = range
+                .start
+                .clamp(line_start, lines[line].end.max(line_start));
+            let column = self.source_code[line_start..start].chars().count();
+            self.text_scroll = line;
+            self.text_hscroll = if self.wrap || column < self.page_width {
+                0
+            } else {
+                column - self.page_width / 2
+            };
+        }
+    }
+
+    /// Moves the selection along the tree structure
+    fn move_selection(&mut self, motion: Motion) {
+        let tree = self.tree.clone();
+        let current = match self.selected_node(tree.root_node()) {
+            Some(node) => node,
+            None => return,
+        };
+        if let Some(node) = motion.apply(current) {
+            self.select_node(node);
+        }
+    }
+
+    /// Jumps between the `Start` and `End` line of the selected node
+    fn jump_to_matching_line(&mut self) {
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        let line_index = self.line_index;
+        let kind = match self.visible_lines().get(line_index) {
+            Some(line) if line.kind != LineKind::Whole => line.kind,
+            _ => return,
+        };
+        let node = match self.selected_node(root) {
+            Some(node) => node,
+            None => return,
+        };
+        let lines = self.visible_lines();
+        let index = if kind == LineKind::End {
+            lines::find(lines, node)
+        } else {
+            lines::find_end(lines, node)
+        };
+        if let Some(index) = index {
+            self.line_index = index;
+        }
+    }
+
+    /// Jumps to the next (or previous) node of kind `kind_term`
+    fn jump_to_kind(&mut self, forward: bool) {
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        let current = match self.selected_node(root) {
+            Some(node) => node,
+            None => return,
+        };
+        if let Some(node) = navigation::node_of_kind(root, current, &self.kind_term, forward) {
+            self.select_node(node);
+        }
+    }
+
+    /// Path of `node` for the title of the tree view, see `title_path`
+    fn title_path(&mut self, root: Node, node: Node) -> &str {
+        if self.title_path.as_ref().map(|(id, _)| *id) != Some(node.id()) {
+            self.title_path = Some((node.id(), NodePath::of(root, node).to_string()));
+        }
+        &self.title_path.as_ref().unwrap().1
+    }
+
+    /// Whether the tree view is currently restricted to search matches
+    fn is_filtered(&self) -> bool {
+        self.filter && !self.search.matches.is_empty()
+    }
+
+    /// The lines shown in the tree view. `line_index` always refers to this
+    /// list, so it has to be used instead of calling `flatten` directly. The
+    /// lines are only flattened again after they were outdated.
+    fn visible_lines(&mut self) -> &[Line] {
+        if self.lines_outdated {
+            let root = self.tree.root_node();
+            let expanded = &self.expanded;
+            let should_collapse = Arc::new(|id| !expanded.contains(&id));
+            self.lines = if self.is_filtered() {
+                flatten_filtered(root, &self.search.matches, should_collapse)
+            } else {
+                flatten(root, 0, should_collapse)
+            };
+            self.lines_outdated = false;
+        }
+        &self.lines
+    }
+
+    /// Node of the selected line
+    fn selected_node<'t>(&mut self, root: Node<'t>) -> Option<Node<'t>> {
+        let line_index = self.line_index;
+        self.visible_lines().get(line_index)?.node(root)
+    }
+
+    /// Collapses or expands `node`
+    fn set_collapsed(&mut self, node: Node, collapsed: bool) {
+        let tree = self.tree.clone();
+        let path = NodePath::of(tree.root_node(), node);
+        if !self.folds.set(&path, !collapsed) {
+            return;
+        }
+        if collapsed {
+            self.expanded.remove(&node.id());
+        } else {
+            self.expanded.insert(node.id());
+        }
+        self.update_lines(node);
+    }
+
+    /// Collapses or expands all nodes below `node`, but not `node` itself
+    fn set_collapsed_below(&mut self, node: Node, collapsed: bool) {
+        let tree = self.tree.clone();
+        let path = NodePath::of(tree.root_node(), node);
+        self.folds.set_below(&path, node, !collapsed);
+        for descendant in lines::descendants(&node)
+            .into_iter()
+            .filter(|node| node.child_count() > 0)
+        {
+            if collapsed {
+                self.expanded.remove(&descendant.id());
+            } else {
+                self.expanded.insert(descendant.id());
+            }
+        }
+        self.update_lines(node);
+    }
+
+    /// Flattens `node` again after it or its descendants were collapsed or
+    /// expanded. If it is visible, only its lines are replaced, and the
+    /// selected line stays selected. A selected line that is folded away
+    /// selects `node` instead.
+    fn update_lines(&mut self, node: Node) {
+        if self.lines_outdated {
+            return;
+        }
+        if self.is_filtered() {
+            // ancestors of matches can't be collapsed, flatten everything
+            self.lines_outdated = true;
+            return;
+        }
+
+        let expanded = &self.expanded;
+        let should_collapse = Arc::new(|id| !expanded.contains(&id));
+        if let Some((start, old, new)) = lines::reflatten(&mut self.lines, node, should_collapse) {
+            if self.line_index >= start + old {
+                self.line_index = self.line_index - old + new;
+            } else if self.line_index > start {
+                self.line_index = start;
+            }
+        }
+    }
+
+    /// Takes the text that arrived on `input`, and parses it if enough time
+    /// has passed since the last parse
+    fn receive_input(&mut self) -> anyhow::Result<()> {
+        self.finish_parsing()?;
+        let mut just_ended = false;
+        if let Some(input) = &self.input {
+            let (text, ended) = input.receive()?;
+            if !text.is_empty() {
+                // the last line may continue in the new text
+                let last_line = self.file_lines.pop().map_or(0, |line| line.start);
+                self.source_code.push_str(&text);
+                self.file_lines.extend(source_view::line_ranges(
+                    &self.source_code,
+                    last_line..self.source_code.len(),
+                ));
+            }
+            if ended {
+                self.input = None;
+                just_ended = true;
+            }
+        }
+        if self.detect && !self.source_code.trim_start().is_empty() {
+            self.detect = false;
+            self.language = language::detect(None, &self.source_code).language();
+            self.set_language(self.language, None);
+        }
+        // once the input ended, the rest is parsed as soon as possible
+        let ended = self.input.is_none();
+        if self.parsed_len < self.source_code.len() && (ended || Instant::now() >= self.next_parse)
+        {
+            self.reparse()?;
+            if self.parsing.is_none() {
+                self.parsed_input();
+            }
+        } else if just_ended {
+            // everything was parsed already
+            self.parsed_input();
+        }
+        Ok(())
+    }
+
+    /// Called after the text that arrived on `input` was parsed. Once all of
+    /// it is parsed, checks for errors like the initial parse, which only saw
+    /// the start of the input.
+    fn parsed_input(&mut self) {
+        if self.input.is_none() && self.parsed_len == self.source_code.len() {
+            self.check_errors();
+        }
+        if self.follow {
+            self.select_newest();
+        }
+    }
+
+    /// Switches to the structless grammar if most of the text are errors,
+    /// unless the grammar was picked by hand or is about to change
+    fn check_errors(&mut self) {
+        if self.language_forced || self.parsing.is_some() || self.tree.language() != self.language {
+            return;
+        }
+        if let Some(fallback) = parse::error_fallback(&self.tree) {
+            self.set_language(tree_sitter_structless::language(), None);
+            self.status = Some(fallback.to_string());
+        }
+    }
+
+    /// Reloads `path` if it changed since it was read. Checks at most twice
+    /// a second, and not while following, which reads the changes anyway.
+    fn check_for_changes(&mut self) -> anyhow::Result<()> {
+        if !self.auto_reload || self.input.is_some() || Instant::now() < self.next_check {
+            return Ok(());
+        }
+        self.next_check = Instant::now() + Duration::from_millis(500);
+        let modified = self
+            .path
+            .as_ref()
+            .and_then(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+        if modified.is_some() && modified != self.modified {
+            self.reload();
+        }
+        Ok(())
+    }
+
+    /// Reloads `path`, showing why if that fails, e.g. because the file was
+    /// removed
+    fn reload(&mut self) {
+        self.reload_error = self.try_reload().err().map(|error| error.to_string());
     }
-}
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
-    let mut f = flatten(app.tree.root_node(), 0, Arc::new(|id| false));
+    /// Reads `path` again and parses it in the background, see
+    /// `finish_parsing`
+    fn try_reload(&mut self) -> anyhow::Result<()> {
+        self.reload_as(self.pending_language())
+    }
+
+    /// Like `try_reload`, but parses the text with `language`. Most of it
+    /// being errors switches to the structless grammar like the initial
+    /// parse, unless the grammar was picked by hand.
+    fn reload_as(&mut self, language: Language) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => anyhow::bail!("stdin can't be reloaded"),
+        };
+        let mut source_code = String::new();
+        File::open(&path)?.read_to_string(&mut source_code)?;
+        self.modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        self.cancel_parsing();
+        // when following, reading goes on from the new end of the file
+        self.input = None;
+
+        let check_errors = !self.language_forced && language == self.language;
+        let parsing = Parsing::start(source_code, language, self.timeout, check_errors);
+        self.parsing = Some((parsing, ParseKind::Reloaded(language)));
+        Ok(())
+    }
+
+    /// Starts or stops following the input. A file is read from where
+    /// `source_code` ends, stdin is read anyway until it ends.
+    fn set_follow(&mut self, follow: bool) -> anyhow::Result<()> {
+        if let Some(path) = &self.path {
+            if follow && self.input.is_none() {
+                self.input = Some(Stream::follow(path, self.source_code.len() as u64)?);
+            } else if !follow {
+                // parse what has arrived so far, nothing more is read
+                self.receive_input()?;
+                self.input = None;
+                if self.parsed_len < self.source_code.len() {
+                    self.reparse()?;
+                }
+            }
+        }
+        self.follow = follow;
+        if follow {
+            self.select_newest();
+        }
+        Ok(())
+    }
 
-    f.iter().for_each(|l| {
-        app.collapsed.insert(l.node.id());
-    });
-    drop(f);
+    /// Selects the last top-level node, the one appended last when following
+    fn select_newest(&mut self) {
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        if let Some(newest) = root
+            .named_child_count()
+            .checked_sub(1)
+            .and_then(|last| root.named_child(last))
+        {
+            self.select_node(newest);
+        }
+    }
 
+    /// Parses `source_code` again, reusing the old tree for the part that was
+    /// parsed before, see `replace_tree`. A lot of new text is parsed in the
+    /// background like the initial input, see `finish_parsing`.
+    fn reparse(&mut self) -> anyhow::Result<()> {
+        if self.parsing.is_some() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let len = self.source_code.len();
+        let new_end = stream::advance(self.parsed_end, &self.source_code[self.parsed_len..]);
+        let mut old_tree = (*self.tree).clone();
+        old_tree.edit(&InputEdit {
+            start_byte: self.parsed_len,
+            old_end_byte: self.parsed_len,
+            new_end_byte: len,
+            start_position: self.parsed_end,
+            old_end_position: self.parsed_end,
+            new_end_position: new_end,
+        });
+
+        if len - self.parsed_len > BACKGROUND_PARSE_LEN {
+            let parsing = Parsing::reparse(self.source_code.clone(), old_tree, self.timeout, false);
+            self.parsing = Some((parsing, ParseKind::Appended));
+            return Ok(());
+        }
+
+        let mut parser = Parser::new();
+        parser.set_language(self.tree.language())?;
+        let tree = parser
+            .parse(&self.source_code, Some(&old_tree))
+            .ok_or_else(|| anyhow::anyhow!("parsing the input failed"))?;
+        self.replace_tree(tree);
+        self.parsed_len = len;
+        self.parsed_end = new_end;
+        // parse less often if parsing is slow
+        self.next_parse = Instant::now() + max(Duration::from_millis(200), started.elapsed() * 4);
+        Ok(())
+    }
+
+    /// Takes the tree of the background parse once it finished, see
+    /// `ParseKind`
+    fn finish_parsing(&mut self) -> anyhow::Result<()> {
+        if !self
+            .parsing
+            .as_ref()
+            .is_some_and(|(parsing, _)| parsing.is_finished())
+        {
+            return Ok(());
+        }
+        let (parsing, kind) = self.parsing.take().unwrap();
+        let (_, len) = parsing.progress();
+        let parsed = match parsing.finish()? {
+            Some(parsed) => parsed,
+            None => return Ok(()),
+        };
+        match kind {
+            ParseKind::Appended => {
+                self.replace_tree(parsed.tree);
+                self.parsed_end =
+                    stream::advance(self.parsed_end, &self.source_code[self.parsed_len..len]);
+                self.parsed_len = len;
+            }
+            ParseKind::Reloaded(_) => {
+                self.file_lines = source_view::line_ranges(&parsed.source, 0..len);
+                self.source_code = parsed.source;
+                self.apply_parse(parsed.tree, len);
+                if self.follow {
+                    self.set_follow(true)?;
+                }
+            }
+            ParseKind::Language { session, .. } => {
+                self.apply_parse(parsed.tree, len);
+                if let Some(session) = session {
+                    self.apply_session(*session, false);
+                }
+            }
+        }
+        if let Some(fallback) = parsed.fallback {
+            self.status = Some(fallback.to_string());
+        }
+        self.parsed_input();
+        Ok(())
+    }
+
+    /// Stops the background parse, if there is one
+    fn cancel_parsing(&mut self) {
+        if let Some((parsing, _)) = self.parsing.take() {
+            parsing.cancel();
+        }
+    }
+
+    /// Grammar of `tree`, or of the tree that is being parsed to replace it
+    fn pending_language(&self) -> Language {
+        match &self.parsing {
+            Some((_, ParseKind::Reloaded(language) | ParseKind::Language { language, .. })) => {
+                *language
+            }
+            _ => self.tree.language(),
+        }
+    }
+
+    /// Switches to `tree`, parsed from a changed `source_code`. The nodes at
+    /// the paths of the expanded and the selected node are expanded and
+    /// selected in the new tree, as far as it still has them.
+    fn replace_tree(&mut self, tree: Tree) {
+        let old_tree = self.tree.clone();
+        let old_root = old_tree.root_node();
+        let selected = self
+            .selected_node(old_root)
+            .map(|node| NodePath::of(old_root, node));
+        let line_index = self.line_index;
+        let on_end = self
+            .visible_lines()
+            .get(line_index)
+            .is_some_and(|line| line.kind == LineKind::End);
+
+        self.tree = Arc::new(tree);
+        self.title_path = None;
+        let tree = self.tree.clone();
+        self.expanded = self.folds.ids(tree.root_node());
+        self.lines_outdated = true;
+        self.line_index = 0;
+
+        if let Some(selected) = selected {
+            let node = selected.resolve_nearest(tree.root_node());
+            self.select_node(node);
+            if on_end {
+                if let Some(index) = lines::find_end(self.visible_lines(), node) {
+                    self.line_index = index;
+                }
+            }
+        }
+    }
+
+    /// Parses `source_code` with `language` instead of the current grammar,
+    /// in the background, see `finish_parsing`. `session` is restored once
+    /// the tree is there.
+    fn set_language(&mut self, language: Language, session: Option<Session>) {
+        if language == self.pending_language() {
+            return;
+        }
+        let reloading = matches!(self.parsing, Some((_, ParseKind::Reloaded(_))));
+        self.cancel_parsing();
+        self.status = None;
+        if reloading {
+            self.reload_error = self.reload_as(language).err().map(|e| e.to_string());
+            return;
+        }
+        if language == self.tree.language() {
+            // only the switch to another grammar is called off
+            return;
+        }
+        let parsing = Parsing::start(self.source_code.clone(), language, self.timeout, false);
+        let session = session.map(Box::new);
+        self.parsing = Some((parsing, ParseKind::Language { language, session }));
+    }
+
+    /// Switches to `tree`, parsed from the first `len` bytes of
+    /// `source_code` at once, see `replace_tree` and `switch_tree`
+    fn apply_parse(&mut self, tree: Tree, len: usize) {
+        self.parsed_len = len;
+        self.parsed_end = stream::advance(Point::new(0, 0), &self.source_code[..len]);
+        self.search = Search::new(
+            &self.search_term,
+            &self.source_code,
+            &tree,
+            &self.search_options,
+        )
+        .unwrap_or_default();
+        if tree.language() == self.tree.language() {
+            self.replace_tree(tree);
+        } else {
+            self.switch_tree(tree);
+        }
+    }
+
+    /// Switches to `tree`, parsed with another grammar. Nodes can't be
+    /// looked up by their paths in a tree of another grammar, so the
+    /// selection and the marks move to the nodes at the same text, and all
+    /// other nodes are collapsed.
+    fn switch_tree(&mut self, tree: Tree) {
+        let old_tree = self.tree.clone();
+        let old_root = old_tree.root_node();
+        // the root stays selected, instead of the first node at its text
+        let selected = self
+            .selected_node(old_root)
+            .filter(|node| *node != old_root)
+            .map(|node| node.byte_range());
+        let mark_ranges: Vec<_> = self
+            .marks
+            .drain()
+            .map(|(name, path)| (name, path.resolve_nearest(old_root).byte_range()))
+            .collect();
+
+        self.tree = Arc::new(tree);
+        self.title_path = None;
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        for (name, range) in mark_ranges {
+            if let Some(node) = root.descendant_for_byte_range(range.start, range.end) {
+                self.marks.insert(name, NodePath::of(root, node));
+            }
+        }
+        self.kinds = navigation::node_kinds(root.language());
+        self.folds = PathSet::default();
+        self.expanded = HashSet::new();
+        self.lines_outdated = true;
+        self.line_index = 0;
+        if let Some(range) = selected {
+            self.reveal(range);
+        }
+    }
+
+    /// Switches to the next language, and requests it, so that `T` switches
+    /// between it and the structless grammar
+    fn cycle_language(&mut self) {
+        let next = LanguageArgument::of(self.pending_language()).next();
+        self.language = next.language();
+        self.language_forced = true;
+        self.set_language(self.language, None);
+    }
+
+    /// Switches between the requested language and the structless grammar
+    fn toggle_structless(&mut self) {
+        let structless = tree_sitter_structless::language();
+        if self.language == structless {
+            self.status = Some("no other grammar to switch to".to_string());
+            return;
+        }
+        self.language_forced = true;
+        if self.pending_language() == structless {
+            self.set_language(self.language, None);
+        } else {
+            self.set_language(structless, None);
+        }
+    }
+
+    /// Selects the node at mark `name`
+    fn jump_to_mark(&mut self, name: char) {
+        let tree = self.tree.clone();
+        if let Some(path) = self.marks.get(&name) {
+            let node = path.resolve_nearest(tree.root_node());
+            self.select_node(node);
+        }
+    }
+
+    /// Restores the grammar, folds, selection, marks, scroll positions,
+    /// search and view mode of the last session with `path`. If the file
+    /// changed since, or `keep_language` and the session used another
+    /// grammar, the nodes are looked up by their paths, as far as the tree
+    /// still has them, and the scroll positions are left alone.
+    fn restore_session(&mut self, keep_language: bool) {
+        let session = match self.path.as_deref().and_then(Session::load) {
+            Some(session) => session,
+            None => return,
+        };
+        let language = session.language.language();
+        if language == self.pending_language() {
+            self.apply_session(session, false);
+        } else if keep_language {
+            self.apply_session(session, true);
+        } else {
+            // like picking the language with `L` or `T` again
+            if language != tree_sitter_structless::language() {
+                self.language = language;
+            }
+            self.language_forced = true;
+            self.set_language(language, Some(session));
+        }
+    }
+
+    /// Restores `session` in the current tree, see `restore_session`.
+    /// `other_language` tells that the session used another grammar.
+    fn apply_session(&mut self, session: Session, other_language: bool) {
+        let changed = if other_language {
+            Some("the last session used another language")
+        } else {
+            (session.content_hash != session::content_hash(&self.source_code))
+                .then_some("file changed since the last session")
+        };
+
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        self.folds = session.folds;
+        self.expanded = self.folds.ids(root);
+        self.lines_outdated = true;
+        self.marks = session.marks;
+        self.view_mode = session.view_mode;
+        self.search_term = session.search_term;
+        self.search_options = session.search_options;
+        if let Ok(search) = Search::new(
+            &self.search_term,
+            &self.source_code,
+            &self.tree,
+            &self.search_options,
+        ) {
+            self.search = search;
+        }
+        self.select_node(session.selected.resolve_nearest(root));
+
+        match changed {
+            None => {
+                self.tree_state.set_offset(session.tree_offset);
+                self.text_scroll = session.text_scroll;
+            }
+            Some(note) if self.status.is_none() => self.status = Some(note.to_string()),
+            Some(_) => {}
+        }
+    }
+
+    /// Saves the session with `path`, see `restore_session`. Failing to do
+    /// so is not worth an error when quitting.
+    fn save_session(&mut self) {
+        let path = match self
+            .path
+            .as_ref()
+            .and_then(|path| fs::canonicalize(path).ok())
+        {
+            Some(path) => path,
+            None => return,
+        };
+        let tree = self.tree.clone();
+        let root = tree.root_node();
+        let session = Session {
+            path,
+            content_hash: session::content_hash(&self.source_code),
+            language: LanguageArgument::of(root.language()),
+            folds: self.folds.clone(),
+            selected: self
+                .selected_node(root)
+                .map(|node| NodePath::of(root, node))
+                .unwrap_or_default(),
+            marks: self.marks.clone(),
+            tree_offset: self.tree_state.offset(),
+            text_scroll: self.text_scroll,
+            search_term: self.search_term.clone(),
+            search_options: self.search_options.clone(),
+            view_mode: self.view_mode,
+        };
+        if let Err(error) = session.save() {
+            debug!(%error, "can't save the session");
+        }
+    }
+
+    /// Switches the filter view on or off, keeping the selected node selected
+    /// if it is still visible
+    fn toggle_filter(&mut self) {
+        let tree = self.tree.clone();
+        let selected_node = self.selected_node(tree.root_node());
+
+        self.filter = !self.filter;
+        self.lines_outdated = true;
+
+        self.line_index = selected_node
+            .and_then(|node| lines::find(self.visible_lines(), node))
+            .unwrap_or(0);
+    }
+}
+
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> anyhow::Result<()> {
     loop {
+        app.receive_input()?;
+        app.check_for_changes()?;
         terminal.draw(|f| ui(f, &mut app))?;
 
-        let flat_lines = flatten(
-            app.tree.root_node(),
-            0,
-            Arc::new(|id| app.collapsed.contains(&id)),
-        );
-        let selected_node = flat_lines[app.line_index].node.id();
+        let tree = app.tree.clone();
+        let selected_node = app.selected_node(tree.root_node()).unwrap();
+        let line_count = match app.view_mode {
+            ViewMode::Tree | ViewMode::Split => app.visible_lines().len(),
+            ViewMode::Text => app.text_lines(selected_node).0.len(),
+        };
+        let page = app.page_height as isize;
 
-        if let Event::Key(key) = event::read()? {
+        // wake up regularly to show the text that arrived on `input`, or to
+        // check if the file changed
+        let waiting = app.input.is_some() || app.parsing.is_some() || app.auto_reload;
+        let event = if !waiting || event::poll(Duration::from_millis(100))? {
+            Some(event::read()?)
+        } else {
+            None
+        };
+        if let Some(Event::Key(key)) = event {
+            let count = app.count.take();
+            // a count beyond the line count can't move further, clamping it
+            // keeps the multiplications below from overflowing
+            let repeat = min(count.unwrap_or(1), max(line_count, 1)) as isize;
             match app.input_mode {
                 InputMode::Normal => match key.code {
+                    KeyCode::Char(name) if app.pending_mark.is_some() => {
+                        if app.pending_mark.take() == Some('m') {
+                            let path = NodePath::of(tree.root_node(), selected_node);
+                            app.marks.insert(name, path);
+                        } else {
+                            app.jump_to_mark(name);
+                        }
+                    }
+                    _ if app.pending_mark.take().is_some() => {}
+                    KeyCode::Char(c @ 'm' | c @ '\'') => {
+                        app.pending_mark = Some(c);
+                    }
+                    KeyCode::Char(c @ '0'..='9') if c != '0' || count.is_some() => {
+                        let digit = c.to_digit(10).unwrap() as usize;
+                        app.count =
+                            Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                    }
                     KeyCode::Char('/') => {
                         app.input_mode = InputMode::Editing;
                     }
+                    KeyCode::Char('?') => {
+                        app.help = !app.help;
+                    }
                     KeyCode::Char('q') => {
+                        app.cancel_parsing();
+                        app.save_session();
                         return Ok(());
                     }
+                    KeyCode::Char('F') => {
+                        app.set_follow(!app.follow)?;
+                    }
+                    KeyCode::Char('R') => {
+                        app.reload();
+                    }
+                    KeyCode::Char('T') => {
+                        app.toggle_structless();
+                    }
+                    KeyCode::Char('L') => {
+                        app.cycle_language();
+                    }
+                    KeyCode::Char('n') => {
+                        if let Some(range) = app.search.next() {
+                            app.show_match(range);
+                        }
+                    }
+                    KeyCode::Char('N') => {
+                        if let Some(range) = app.search.previous() {
+                            app.show_match(range);
+                        }
+                    }
+                    KeyCode::Char('f') => {
+                        app.toggle_filter();
+                    }
+                    KeyCode::Char('k') => {
+                        app.input_mode = InputMode::Kind;
+                    }
+                    KeyCode::Char('p') => {
+                        app.move_selection(Motion::Parent);
+                    }
+                    KeyCode::Char('c')
+                        if key.modifiers.contains(KeyModifiers::CONTROL)
+                            && app.parsing.is_some() =>
+                    {
+                        app.cancel_parsing();
+                        app.status = Some("parsing cancelled".to_string());
+                    }
+                    KeyCode::Char('c') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.move_selection(Motion::FirstChild);
+                    }
+                    KeyCode::Char('C') => {
+                        app.move_selection(Motion::LastChild);
+                    }
+                    KeyCode::Char('S') => {
+                        app.move_selection(Motion::NextSibling);
+                    }
+                    KeyCode::Char('W') => {
+                        app.move_selection(Motion::PreviousSibling);
+                    }
+                    KeyCode::Char('%') => {
+                        app.jump_to_matching_line();
+                    }
+                    KeyCode::Char(']') => {
+                        app.jump_to_kind(true);
+                    }
+                    KeyCode::Char('[') => {
+                        app.jump_to_kind(false);
+                    }
                     KeyCode::Down | KeyCode::Char('s') => {
-                        app.line_index = min(app.line_index + 1, flat_lines.len() - 1);
+                        app.move_by(repeat, line_count);
                     }
                     KeyCode::Up | KeyCode::Char('w') => {
-                        app.line_index = max(app.line_index, 1) - 1;
+                        app.move_by(-repeat, line_count);
+                    }
+                    KeyCode::PageDown | KeyCode::Char(' ') => {
+                        app.move_by(repeat.saturating_mul(page), line_count);
+                    }
+                    KeyCode::PageUp | KeyCode::Char('b') => {
+                        app.move_by(-repeat.saturating_mul(page), line_count);
+                    }
+                    KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.move_by(repeat.saturating_mul(max(page / 2, 1)), line_count);
+                    }
+                    KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.move_by(-repeat.saturating_mul(max(page / 2, 1)), line_count);
+                    }
+                    KeyCode::Home | KeyCode::Char('g') => {
+                        app.move_to(count.map_or(0, |n| n.saturating_sub(1)), line_count);
+                    }
+                    KeyCode::End | KeyCode::Char('G') => {
+                        app.move_to(
+                            count.map_or(line_count, |n| n.saturating_sub(1)),
+                            line_count,
+                        );
+                    }
+                    KeyCode::Right | KeyCode::Char('d') if app.view_mode == ViewMode::Text => {
+                        app.text_hscroll = app.text_hscroll.saturating_add(
+                            (repeat as usize).saturating_mul(max(app.page_width / 2, 1)),
+                        );
+                    }
+                    KeyCode::Left | KeyCode::Char('a') if app.view_mode == ViewMode::Text => {
+                        app.text_hscroll = app.text_hscroll.saturating_sub(
+                            (repeat as usize).saturating_mul(max(app.page_width / 2, 1)),
+                        );
+                    }
+                    KeyCode::Char('z') => {
+                        app.wrap = !app.wrap;
+                        app.text_hscroll = 0;
+                    }
+                    KeyCode::Char('x') => {
+                        app.context = !app.context;
+                        app.scroll_to_selection();
+                    }
+                    KeyCode::Char('v') => {
+                        app.view_mode = if app.view_mode == ViewMode::Split {
+                            ViewMode::Tree
+                        } else {
+                            ViewMode::Split
+                        };
+                    }
+                    KeyCode::Char('|') => {
+                        app.split_direction = if app.split_direction == Direction::Horizontal {
+                            Direction::Vertical
+                        } else {
+                            Direction::Horizontal
+                        };
+                    }
+                    KeyCode::Char('>') => {
+                        app.split_ratio = min(app.split_ratio + 5, 90);
+                    }
+                    KeyCode::Char('<') => {
+                        app.split_ratio = max(app.split_ratio - 5, 10);
                     }
                     KeyCode::Right | KeyCode::Char('d') => {
                         // already expanded ? => expand children
-                        if !app.collapsed.contains(&selected_node) {
-                            get_node_decedents(&flat_lines[app.line_index].node)
-                                .iter()
-                                .for_each(|decedent| {
-                                    app.collapsed.remove(&decedent.id());
-                                })
+                        if app.expanded.contains(&selected_node.id()) {
+                            app.set_collapsed_below(selected_node, false);
                         } else {
-                            app.collapsed.remove(&selected_node);
+                            app.set_collapsed(selected_node, false);
                         }
                     }
                     KeyCode::Left | KeyCode::Char('a') => {
                         // already collapsed ? => collapse children
-                        if app.collapsed.contains(&selected_node) {
-                            get_node_decedents(&flat_lines[app.line_index].node)
-                                .iter()
-                                .for_each(|decedent| {
-                                    app.collapsed.insert(decedent.id());
-                                })
+                        if !app.expanded.contains(&selected_node.id()) {
+                            app.set_collapsed_below(selected_node, true);
                         } else {
-                            app.collapsed.insert(selected_node);
+                            app.set_collapsed(selected_node, true);
                         }
                     }
                     KeyCode::Enter => {
@@ -212,12 +1351,43 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                         } else {
                             ViewMode::Text
                         };
+                        app.scroll_to_selection();
                     }
                     _ => {}
                 },
                 InputMode::Editing => match key.code {
                     KeyCode::Enter => {
-                        // app.messages.push(app.input.drain(..).collect());
+                        match Search::new(
+                            &app.search_term,
+                            &app.source_code,
+                            &app.tree,
+                            &app.search_options,
+                        ) {
+                            Ok(search) => {
+                                app.search = search;
+                                app.search_error = None;
+                                app.lines_outdated = true;
+                                if let Some(range) = app.search.first() {
+                                    app.show_match(range);
+                                }
+                                app.input_mode = InputMode::Normal;
+                            }
+                            Err(error) => {
+                                app.search_error = Some(error.to_string());
+                            }
+                        }
+                    }
+                    KeyCode::Char('q') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        app.search_options.query = !app.search_options.query;
+                    }
+                    KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        app.search_options.regex = !app.search_options.regex;
+                    }
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        app.search_options.case = app.search_options.case.cycle();
+                    }
+                    KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        app.search_options.whole_word = !app.search_options.whole_word;
                     }
                     KeyCode::Char(c) => {
                         app.search_term.push(c);
@@ -230,111 +1400,145 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                     }
                     _ => {}
                 },
+                InputMode::Kind => match key.code {
+                    KeyCode::Enter => {
+                        app.kind_candidates.clear();
+                        app.jump_to_kind(true);
+                        app.input_mode = InputMode::Normal;
+                    }
+                    KeyCode::Tab => {
+                        app.kind_candidates =
+                            navigation::complete_kind(&mut app.kind_term, &app.kinds);
+                    }
+                    KeyCode::Char(c) => {
+                        app.kind_term.push(c);
+                    }
+                    KeyCode::Backspace => {
+                        app.kind_term.pop();
+                    }
+                    KeyCode::Esc => {
+                        app.kind_candidates.clear();
+                        app.input_mode = InputMode::Normal;
+                    }
+                    _ => {}
+                },
             }
         }
-        trace!(?app, "new state: ");
+        // the whole `app` is too large to log on every key press
+        trace!(line_index = app.line_index, view_mode = ?app.view_mode, "new state: ");
     }
 }
 
-fn get_node_decedents<'a, 'b>(node: &'b Node<'a>) -> Vec<Node<'a>> {
-    let mut cursor = node.walk();
-
-    node.children(&mut cursor)
-        .into_iter()
-        .flat_map(|child| get_node_decedents(&child).into_iter().chain([child]))
-        .collect()
-}
-
-/// Gets the range of the first child node, that is actually smaller than
-/// `node`
-#[instrument(fields(start=node.start_byte(), end=node.end_byte(), id=node.id()))]
-fn get_smaller_child_range(node: &Node) -> Option<Range> {
-    let number = node.child_count();
-    if number == 0 {
-        None
+fn trim_string(s: &str, max_length: usize) -> String {
+    if s.len() > max_length {
+        let trimmed = &s[0..max_length - 1];
+        format!("{}…", trimmed)
     } else {
-        let child_start = node.child(0).unwrap();
-        let child_end = node.child(number - 1).unwrap();
-        trace!(?child_start, ?child_end, "children");
-
-        if child_start.start_byte() > node.start_byte() || child_end.end_byte() < node.end_byte() {
-            Some(span_ranges(child_start, child_end))
-        } else {
-            get_smaller_child_range(&child_start)
-        }
-    }
-}
-
-fn span_ranges(start: Node, end: Node) -> Range {
-    Range {
-        start_byte: start.start_byte(),
-        start_point: start.start_position(),
-        end_byte: end.end_byte(),
-        end_point: end.end_position(),
+        " ".repeat(max_length - s.len()) + s
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum LineKind {
-    Start,
-    End,
-    Whole,
-}
-
-#[derive(Debug)]
-struct Line<'a> {
-    node: Node<'a>,
-    indent: usize,
-    kind: LineKind,
-}
+/// Keys of the normal mode, shown after pressing `?`
+const NORMAL_KEYS: &[(&str, &str)] = &[
+    ("q", "to exit"),
+    ("/", "to search"),
+    ("n N", "for next/previous match"),
+    ("f", "to filter"),
+    ("F", "to follow"),
+    ("R", "to reload"),
+    ("Ctrl-c", "to stop parsing"),
+    ("T", "to toggle structless"),
+    ("L", "to switch the language"),
+    ("m '", "to mark/jump"),
+    ("k", "to pick a node kind"),
+    ("] [", "for next/previous of that kind"),
+    ("p", "parent"),
+    ("c C", "first/last child"),
+    ("S W", "next/previous sibling"),
+    ("%", "start/end"),
+    ("z", "to wrap text"),
+    ("x", "to show the whole file"),
+    ("v", "to split"),
+    ("| < >", "to arrange panes"),
+    ("?", "to hide this help"),
+];
 
-fn flatten<F>(node: Node, indent: usize, should_collapse: Arc<F>) -> Vec<Line>
-where
-    F: Fn(usize) -> bool,
-{
-    use LineKind::*;
-    if should_collapse(node.id()) || node.child_count() == 0 {
-        vec![Line {
-            node,
-            indent,
-            kind: Whole,
-        }]
-    } else {
-        let mut lines = Vec::new();
+const EDITING_KEYS: &[(&str, &str)] = &[
+    ("Esc", "to stop editing"),
+    ("Enter", "to search"),
+    ("Alt-r", "regex"),
+    ("Alt-c", "case"),
+    ("Alt-w", "whole word"),
+    ("Alt-q", "tree-sitter query"),
+];
 
-        lines.push(Line {
-            node,
-            indent,
-            kind: Start,
-        });
-        for child in node.children(&mut node.walk()) {
-            lines.append(&mut flatten(child, indent + 1, should_collapse.clone()))
+/// Help line like "Press q to exit, / to search" with the keys in bold.
+/// Alternative keys are separated by spaces, e.g. "n N" is shown as "n/N".
+fn key_help(entries: &[(&'static str, &'static str)]) -> Vec<Span<'static>> {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let mut spans = vec![Span::raw("Press ")];
+    for (i, (keys, description)) in entries.iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::raw(", "));
         }
-        lines.push(Line {
-            node,
-            indent,
-            kind: End,
-        });
-        lines
-    }
-}
-
-fn trim_string(s: &str, max_length: usize) -> String {
-    if (s.len() > max_length) {
-        let trimmed = &s[0..max_length - 1];
-        format!("{}…", trimmed)
-    } else {
-        " ".repeat(max_length - s.len()) + s
+        for (j, key) in keys.split(' ').enumerate() {
+            if j > 0 {
+                spans.push(Span::raw("/"));
+            }
+            spans.push(Span::styled(key, bold));
+        }
+        spans.push(Span::raw(format!(" {}", description)));
     }
+    spans
 }
 
 fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let (msg, style) = match app.input_mode {
+        InputMode::Normal => {
+            // what changes comes first, so that it is never cut off
+            let mut msg = vec![
+                Span::raw(app.count.map(|n| format!("{} ", n)).unwrap_or_default()),
+                Span::raw(format!("{} ", app.line_index)),
+                Span::raw(format!("{} ", app.search.status())),
+            ];
+            if app.help {
+                msg.extend(key_help(NORMAL_KEYS));
+            } else {
+                msg.extend(key_help(&[("?", "for help"), ("q", "to exit")]));
+            }
+            (msg, Style::default().add_modifier(Modifier::RAPID_BLINK))
+        }
+        InputMode::Editing => (key_help(EDITING_KEYS), Style::default()),
+        InputMode::Kind => {
+            let mut msg = key_help(&[
+                ("Esc", "to stop editing"),
+                ("Enter", "to jump"),
+                ("Tab", "to complete"),
+            ]);
+            msg.push(Span::raw(". "));
+            if !app.kind_candidates.is_empty() {
+                msg.push(Span::styled(
+                    app.kind_candidates.join(" "),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            (msg, Style::default())
+        }
+    };
+    // the help of all keys wraps onto as many rows as it needs
+    let width = f.size().width.saturating_sub(4).max(1) as usize;
+    let help_width: usize = msg.iter().map(|span| span.content.width()).sum();
+    // wrapping at spaces leaves the ends of rows empty, keep a row for that
+    let help_height = match help_width.div_ceil(width) {
+        0 | 1 => 1,
+        rows => rows + 1,
+    };
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(2)
         .constraints(
             [
-                Constraint::Length(1),
+                Constraint::Length(help_height as u16),
                 Constraint::Length(3),
                 Constraint::Min(1),
             ]
@@ -342,145 +1546,380 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         )
         .split(f.size());
 
-    let (msg, style) = match app.input_mode {
-        InputMode::Normal => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit, "),
-                Span::styled("/", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to search."),
-                Span::raw(format!("{}", app.line_index)),
-            ],
-            Style::default().add_modifier(Modifier::RAPID_BLINK),
-        ),
-        InputMode::Editing => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to stop editing, "),
-                Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to record the message"),
-            ],
-            Style::default(),
-        ),
-    };
     let mut text = Text::from(Spans::from(msg));
     text.patch_style(style);
-    let help_message = Paragraph::new(text);
+    let help_message = Paragraph::new(text).wrap(Wrap { trim: true });
     f.render_widget(help_message, chunks[0]);
 
-    let input = Paragraph::new(app.search_term.as_ref())
+    let (input_title, input_text) = match (&app.input_mode, &app.search_error) {
+        (InputMode::Kind, _) => (Span::raw("Node kind"), &app.kind_term),
+        (_, Some(error)) => (
+            Span::styled(
+                format!("Input ({})", error.lines().last().unwrap_or_default()),
+                Style::default().fg(Color::Red),
+            ),
+            &app.search_term,
+        ),
+        (_, None) => (
+            Span::raw(format!("Input ({})", app.search_options)),
+            &app.search_term,
+        ),
+    };
+    let input = Paragraph::new(input_text.as_ref())
         .style(match app.input_mode {
             InputMode::Normal => Style::default(),
-            InputMode::Editing => Style::default().fg(Color::Yellow),
+            InputMode::Editing | InputMode::Kind => Style::default().fg(Color::Yellow),
         })
-        .block(Block::default().borders(Borders::ALL).title("Input"));
+        .block(Block::default().borders(Borders::ALL).title(input_title));
     f.render_widget(input, chunks[1]);
     match app.input_mode {
         InputMode::Normal =>
             // Hide the cursor. `Frame` does this by default, so we don't need to do anything here
             {}
 
-        InputMode::Editing => {
+        InputMode::Editing | InputMode::Kind => {
             // Make the cursor visible and ask tui-rs to put it at the specified coordinates after rendering
             f.set_cursor(
                 // Put cursor past the end of the input text
-                chunks[1].x + app.search_term.width() as u16 + 1,
+                chunks[1].x + input_text.width() as u16 + 1,
                 // Move one line down, from the border to the input line
                 chunks[1].y + 1,
             )
         }
     }
 
-    let node_start = app.tree.root_node().byte_range().start;
-    let child_range = get_smaller_child_range(&app.tree.root_node())
-        .map(|it| it.start_byte)
-        .unwrap_or(0);
-    let r = &app.source_code[node_start..child_range];
-    trace!("{}, {}, {}", r, node_start, child_range);
-
-    let flat_lines = flatten(
-        app.tree.root_node(),
-        0,
-        Arc::new(|id| app.collapsed.contains(&id)),
-    );
+    let tree = app.tree.clone();
+    let root = tree.root_node();
+    app.line_index = min(app.line_index, app.visible_lines().len() - 1);
+    let selected_node = app.selected_node(root).unwrap();
+    let (main_area, preview_area) = if app.view_mode == ViewMode::Split {
+        let panes = Layout::default()
+            .direction(app.split_direction.clone())
+            .constraints(
+                [
+                    Constraint::Percentage(app.split_ratio),
+                    Constraint::Percentage(100 - app.split_ratio),
+                ]
+                .as_ref(),
+            )
+            .split(chunks[2]);
+        (panes[0], Some(panes[1]))
+    } else {
+        (chunks[2], None)
+    };
+    // area inside the borders of the tree or text block
+    app.page_height = max(main_area.height.saturating_sub(2), 1) as usize;
+    app.page_width = max(main_area.width.saturating_sub(2), 1) as usize;
 
-    app.list_state.select(Some(app.line_index));
+    app.tree_state.select(Some(app.line_index));
 
     match app.view_mode {
-        ViewMode::Tree => {
-            let items: Vec<_> = flat_lines
-                .iter()
-                .filter(|line| {
-                    if app.search_term.is_empty() {
-                        true
-                    } else {
-                        line.node
-                            .utf8_text(app.source_code.as_bytes())
-                            .unwrap()
-                            .contains(&app.search_term)
-                    }
-                })
-                .map(|line| {
-                    let start = line.node.start_byte();
-                    let end = line.node.end_byte();
-                    let node_selected = line.node.id() == flat_lines[app.line_index].node.id();
-
-                    if line.kind == LineKind::End {
-                        ListItem::new(Spans::from(vec![Span::styled(
-                            format!("{}// end {}", " ".repeat(line.indent), line.node.kind()),
-                            if node_selected {
-                                Style::default().fg(Color::Red)
+        ViewMode::Tree => render_tree(f, app, main_area, root),
+        ViewMode::Text => render_text(f, app, main_area, selected_node),
+        ViewMode::Split => {
+            render_tree(f, app, main_area, root);
+            if let Some(preview_area) = preview_area {
+                render_preview(f, app, preview_area, selected_node);
+            }
+        }
+    }
+}
+
+/// Renders the tree view. Only the lines that fit into `area` are looked at,
+/// so this is cheap no matter how large the tree is.
+fn render_tree<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect, root: Node) {
+    // borders and highlight symbol
+    let row_width = (area.width as usize).saturating_sub(4);
+    let line_count = app.visible_lines().len();
+    let rows = app
+        .tree_state
+        .get_items_bounds(line_count, area.height.saturating_sub(2) as usize);
+    let line_index = app.line_index;
+    let selected = app.visible_lines()[line_index].clone();
+    let lines = app.visible_lines()[rows.clone()].to_vec();
+    let in_selected_subtree = |i: usize, line: &Line| {
+        i == line_index
+            || (i > line_index
+                && selected.kind == LineKind::Start
+                && (line.id == selected.id
+                    || (line.indent > selected.indent
+                        && selected.range.start <= line.range.start
+                        && line.range.end <= selected.range.end)))
+    };
+    let items: Vec<_> = lines
+        .iter()
+        .zip(rows)
+        .map(|(line, i)| {
+            let node = line.node(root).expect("lines belong to the current tree");
+            let start = node.start_byte();
+            let end = node.end_byte();
+            let node_selected = line.id == selected.id;
+            let marker = match line.kind {
+                LineKind::Start => FoldMarker::Expanded,
+                LineKind::End => FoldMarker::End,
+                LineKind::Whole if node.child_count() > 0 => FoldMarker::Collapsed,
+                LineKind::Whole => FoldMarker::None,
+            };
+            // indent guides and fold marker are drawn by the tree widget
+            let prefix = (line.indent + 1) * tree::INDENT_WIDTH;
+
+            let rows = preview::row_count(&node);
+            // single line nodes are shown as they are, even when collapsed
+            let fold = if line.kind != LineKind::Whole || rows > 1 {
+                Fold::new(&node, &app.source_code)
+            } else {
+                None
+            };
+
+            let spans = if line.kind == LineKind::End {
+                let style = if node_selected {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                };
+                match fold {
+                    Some(fold) => {
+                        let mut spans = vec![Span::raw(" ".repeat(15 + 3))];
+                        let width = row_width.saturating_sub(prefix + 15 + 3);
+                        let text = preview::compact(&app.source_code, fold.footer.clone(), width);
+                        spans.append(&mut preview::highlight(
+                            &text,
+                            fold.footer,
+                            &app.search,
+                            style,
+                        ));
+                        spans
+                    }
+                    None => vec![Span::styled(format!("// end {}", node.kind()), style)],
+                }
+            } else {
+                let mut spans = vec![
+                    Span::styled(
+                        trim_string(node.kind(), 15),
+                        Style::default()
+                            .fg(Color::DarkGray)
+                            .add_modifier(if node_selected {
+                                Modifier::empty()
                             } else {
-                                Style::default().fg(Color::DarkGray)
-                            },
-                        )]))
-                    } else {
-                        ListItem::new(Spans::from(vec![
-                            Span::raw(" ".repeat(line.indent)),
-                            Span::styled(
-                                trim_string(line.node.kind(), 15),
-                                Style::default().fg(Color::DarkGray).add_modifier(
-                                    if node_selected {
-                                        Modifier::empty()
-                                    } else {
-                                        Modifier::DIM
-                                    },
-                                ),
-                            ),
-                            Span::raw("   "),
-                            Span::styled(
-                                app.source_code[start..end].to_string(),
-                                Style::default().fg(Color::Cyan),
-                            ),
-                        ]))
-                    }
-                })
-                .collect();
-            let list = List::new(items)
-                .block(Block::default().title("Tree").borders(Borders::ALL))
-                .style(Style::default().fg(Color::White))
-                .highlight_style(
-                    Style::default()
-                        .add_modifier(Modifier::ITALIC)
-                        .bg(Color::Rgb(30, 30, 30)),
+                                Modifier::DIM
+                            }),
+                    ),
+                    Span::raw("   "),
+                ];
+                let mut width = row_width.saturating_sub(prefix + 15 + 3);
+
+                let hint = (rows > 1 || end - start > width)
+                    .then(|| preview::size_hint(end - start, rows));
+                if let Some(hint) = &hint {
+                    width = width.saturating_sub(hint.len() + 1);
+                }
+
+                let style = Style::default().fg(Color::Cyan);
+                match (&line.kind, fold) {
+                    (LineKind::Start, Some(fold)) => {
+                        let text = preview::compact(&app.source_code, fold.header.clone(), width);
+                        spans.append(&mut preview::highlight(
+                            &text,
+                            fold.header,
+                            &app.search,
+                            style,
+                        ));
+                    }
+                    (_, Some(fold)) => {
+                        let summary = fold.summary();
+                        let width = width.saturating_sub(summary.width());
+                        let footer =
+                            preview::compact(&app.source_code, fold.footer.clone(), width / 2);
+                        let header = preview::compact(
+                            &app.source_code,
+                            fold.header.clone(),
+                            width - footer.len(),
+                        );
+                        spans.append(&mut preview::highlight(
+                            &header,
+                            fold.header,
+                            &app.search,
+                            style,
+                        ));
+                        spans.push(Span::styled(summary, Style::default().fg(Color::DarkGray)));
+                        spans.append(&mut preview::highlight(
+                            &footer,
+                            fold.footer,
+                            &app.search,
+                            style,
+                        ));
+                    }
+                    (_, None) => {
+                        let text = preview::compact(&app.source_code, start..end, width);
+                        spans.append(&mut preview::highlight(
+                            &text,
+                            start..end,
+                            &app.search,
+                            style,
+                        ));
+                    }
+                }
+                if let Some(hint) = hint {
+                    spans.push(Span::styled(
+                        format!(" {}", hint),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+                spans
+            };
+            tree::TreeItem::new(Spans::from(spans), line.indent, marker)
+                .in_selected_subtree(in_selected_subtree(i, line))
+        })
+        .collect();
+    let mut notes = Vec::new();
+    if app.is_filtered() {
+        notes.push("filtered".to_string());
+    }
+    if let Some(status) = &app.status {
+        notes.push(status.clone());
+    }
+    if let Some(error) = &app.reload_error {
+        notes.push(format!("reloading failed: {}", error));
+    }
+    let size = preview::format_size(app.source_code.len());
+    if app.follow {
+        notes.push(format!("following, {}", size));
+    } else if app.input.is_some() {
+        notes.push(format!("still reading, {} so far", size));
+    }
+    if let Some((parsing, _)) = &app.parsing {
+        let (consumed, len) = parsing.progress();
+        let language = match parsing.falling_back() {
+            Some(_) => " as structless".to_string(),
+            None if app.pending_language() != root.language() => {
+                format!(
+                    " as {}",
+                    LanguageArgument::of(app.pending_language()).name()
                 )
-                .highlight_symbol(">>");
-            f.render_stateful_widget(list, chunks[2], &mut app.list_state);
-        }
-        ViewMode::Text => {
-            let block = Block::default().borders(Borders::ALL);
-            let selected_node = flat_lines[app.line_index].node;
-            let range = selected_node.start_byte()..selected_node.end_byte();
+            }
+            None => "".to_string(),
+        };
+        notes.push(format!(
+            "parsing{}, {} of {}",
+            language,
+            preview::format_size(consumed),
+            preview::format_size(len)
+        ));
+    }
+    // the language and where the selected node is, e.g. `Tree [rust] /0/body`
+    let language = LanguageArgument::of(root.language()).name();
+    let path = selected
+        .node(root)
+        .map(|node| app.title_path(root, node).to_string())
+        .unwrap_or_default();
+    let title = if notes.is_empty() {
+        format!("Tree [{}] {}", language, path)
+    } else {
+        format!("Tree [{}] {} ({})", language, path, notes.join(", "))
+    };
+    let tree = tree::Tree::new(items)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .style(Style::default().fg(Color::White))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::ITALIC)
+                .bg(Color::Rgb(30, 30, 30)),
+        )
+        .subtree_style(Style::default().bg(Color::Rgb(18, 18, 18)))
+        .guide_style(Style::default().fg(Color::DarkGray))
+        .highlight_symbol(">>");
+    f.render_stateful_widget(tree, area, &mut app.tree_state);
+}
+
+fn render_text<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect, selected_node: Node) {
+    let block = Block::default().borders(Borders::ALL);
+    let (lines, first_row, bounds) = app.text_lines(selected_node);
+    let text_scroll = min(app.text_scroll, lines.len() - 1);
 
-            let paragraph = Paragraph::new(Text::raw(app.source_code[range].to_string()))
-                .block(block.clone().title("Source Code"))
-                .wrap(Wrap { trim: false })
-                .scroll((0, 0));
-            f.render_widget(paragraph, chunks[2]);
+    let text = source_view::numbered_lines(
+        &app.source_code,
+        lines,
+        bounds,
+        first_row,
+        text_scroll,
+        if app.wrap { 0 } else { app.text_hscroll },
+        app.page_height,
+        app.context.then(|| selected_node.byte_range()),
+        &app.search,
+    );
+    app.text_scroll = text_scroll;
+    let title = format!(
+        "Source Code ({}lines {}-{})",
+        if app.context { "whole file, " } else { "" },
+        selected_node.start_position().row + 1,
+        selected_node.end_position().row + 1
+    );
+    let mut paragraph = Paragraph::new(text).block(block.title(title));
+    if app.wrap {
+        paragraph = paragraph.wrap(Wrap { trim: false });
+    }
+    f.render_widget(paragraph, area);
+}
+
+/// Loading screen shown while the input is parsed
+fn render_progress<B: Backend>(f: &mut Frame<B>, parsing: &Parsing) {
+    let area = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+        .split(f.size())[0];
+    let (consumed, len) = parsing.progress();
+    let title = match parsing.falling_back() {
+        Some(Fallback::TimedOut) => {
+            "Parsing timed out, parsing as structless instead (q to cancel)"
         }
+        Some(Fallback::Errors(_)) => {
+            "Too many parse errors, parsing as structless instead (q to cancel)"
+        }
+        None => "Parsing (q to cancel)",
+    };
+    let gauge = Gauge::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .gauge_style(Style::default().fg(Color::Cyan))
+        .ratio(if len == 0 {
+            1.0
+        } else {
+            consumed as f64 / len as f64
+        })
+        .label(format!(
+            "{} of {}",
+            preview::format_size(consumed),
+            preview::format_size(len)
+        ));
+    f.render_widget(gauge, area);
+}
+
+/// Source pane of `ViewMode::Split`. Shows the whole file around `node` and
+/// follows the selection.
+fn render_preview<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect, node: Node) {
+    let height = area.height.saturating_sub(2) as usize;
+    let text = source_view::numbered_lines(
+        &app.source_code,
+        &app.file_lines,
+        0..app.source_code.len(),
+        0,
+        node.start_position().row.saturating_sub(height / 4),
+        0,
+        height,
+        Some(node.byte_range()),
+        &app.search,
+    );
+    let title = format!(
+        "Source Code (lines {}-{})",
+        node.start_position().row + 1,
+        node.end_position().row + 1
+    );
+    let mut paragraph =
+        Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(title));
+    if app.wrap {
+        paragraph = paragraph.wrap(Wrap { trim: false });
     }
+    f.render_widget(paragraph, area);
 }
 
 /// Structure-Aware Less
@@ -491,16 +1930,24 @@ struct Args {
     #[clap(short, long, value_hint=clap::ValueHint::FilePath)]
     input: String,
 
-    /// Language to use for parsing the file
-    #[clap(arg_enum, default_value = "unknown")]
-    language: LanguageArgument,
-}
+    /// Language to use for parsing the file, detected from the file name and
+    /// content if not given
+    #[clap(arg_enum)]
+    language: Option<LanguageArgument>,
+
+    /// Seconds after which parsing falls back to the structless grammar, 0
+    /// to never give up
+    #[clap(long, default_value = "10")]
+    timeout: u64,
+
+    /// Keep reading what is appended to the file and select the newest
+    /// top-level node, like `tail -f`
+    #[clap(short, long)]
+    follow: bool,
 
-#[derive(clap::ArgEnum, Clone, Debug)]
-enum LanguageArgument {
-    Rust,
-    Json,
-    Unknown,
+    /// Reload the file when it changes
+    #[clap(short, long)]
+    reload: bool,
 }
 
 fn main() -> Result<(), anyhow::Error> {
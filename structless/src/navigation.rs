@@ -0,0 +1,171 @@
+use tree_sitter::{Language, Node};
+
+/// Structural movements from one node to a related one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Parent,
+    FirstChild,
+    LastChild,
+    NextSibling,
+    PreviousSibling,
+}
+
+impl Motion {
+    /// The node reached by this motion. Only named nodes are considered for
+    /// children and siblings, so punctuation like `,` is skipped.
+    pub fn apply<'t>(self, node: Node<'t>) -> Option<Node<'t>> {
+        match self {
+            Motion::Parent => node.parent(),
+            Motion::FirstChild => node.named_child(0),
+            Motion::LastChild => node
+                .named_child_count()
+                .checked_sub(1)
+                .and_then(|last| node.named_child(last)),
+            Motion::NextSibling => node.next_named_sibling(),
+            Motion::PreviousSibling => node.prev_named_sibling(),
+        }
+    }
+}
+
+/// Names of all named node kinds of `language`, sorted and without
+/// duplicates. These are the same kinds that are listed in the grammar's
+/// `node-types.json`.
+pub fn node_kinds(language: Language) -> Vec<&'static str> {
+    let mut kinds: Vec<_> = (0..language.node_kind_count() as u16)
+        .filter(|&id| language.node_kind_is_named(id) && language.node_kind_is_visible(id))
+        .filter_map(|id| language.node_kind_for_id(id))
+        .collect();
+    kinds.sort_unstable();
+    kinds.dedup();
+    kinds
+}
+
+/// Completes `prefix` to the longest prefix shared by all `kinds` starting
+/// with it. Returns the candidates that are still possible.
+pub fn complete_kind<'k>(prefix: &mut String, kinds: &[&'k str]) -> Vec<&'k str> {
+    let candidates: Vec<_> = kinds
+        .iter()
+        .copied()
+        .filter(|kind| kind.starts_with(prefix.as_str()))
+        .collect();
+    if let Some(first) = candidates.first() {
+        let common = candidates.iter().fold(first.len(), |len, kind| {
+            first
+                .bytes()
+                .zip(kind.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        *prefix = first[..common].to_string();
+    }
+    candidates
+}
+
+/// Finds the next (or previous) node of the given kind after `current` in
+/// document order, wrapping around at the end of the file
+pub fn node_of_kind<'t>(
+    root: Node<'t>,
+    current: Node<'t>,
+    kind: &str,
+    forward: bool,
+) -> Option<Node<'t>> {
+    let mut before = None;
+    let mut after = None;
+    let mut passed_current = false;
+
+    let mut cursor = root.walk();
+    loop {
+        let node = cursor.node();
+        if node.id() == current.id() {
+            passed_current = true;
+        } else if node.kind() == kind {
+            match (forward, passed_current) {
+                (true, false) => before = before.or(Some(node)),
+                (true, true) => {
+                    after = Some(node);
+                    break;
+                }
+                (false, false) => before = Some(node),
+                (false, true) => after = Some(node),
+            }
+        }
+
+        if cursor.goto_first_child() || cursor.goto_next_sibling() {
+            continue;
+        }
+        while cursor.goto_parent() {
+            if cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        if cursor.node().id() == root.id() {
+            break;
+        }
+    }
+
+    if forward {
+        after.or(before)
+    } else {
+        before.or(after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    #[test]
+    fn finds_nodes_of_kind_with_wrap_around() {
+        let source = r#"["a" ("b") "c"]"#;
+        let mut parser = Parser::new();
+        parser
+            .set_language(tree_sitter_structless::language())
+            .unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+
+        let next = node_of_kind(root, root, "string", true).unwrap();
+        assert_eq!(next.byte_range(), 1..4);
+        let next = node_of_kind(root, next, "string", true).unwrap();
+        assert_eq!(next.byte_range(), 6..9);
+        let previous = node_of_kind(root, root, "string", false).unwrap();
+        assert_eq!(previous.byte_range(), 11..14);
+    }
+
+    #[test]
+    fn motions() {
+        let source = r#"["a" "b" "c"]"#;
+        let mut parser = Parser::new();
+        parser
+            .set_language(tree_sitter_structless::language())
+            .unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let delimited = tree.root_node().child(0).unwrap().child(0).unwrap();
+        assert_eq!(delimited.kind(), "delimited");
+
+        let first = Motion::FirstChild.apply(delimited).unwrap();
+        assert_eq!(first.kind(), "bracket_start");
+        let last = Motion::LastChild.apply(delimited).unwrap();
+        assert_eq!(last.kind(), "bracket_end");
+        let second = Motion::NextSibling.apply(first).unwrap();
+        assert_eq!(second.byte_range(), 1..4);
+        assert_eq!(
+            Motion::PreviousSibling.apply(second).unwrap().id(),
+            first.id()
+        );
+        assert_eq!(Motion::Parent.apply(second).unwrap().id(), delimited.id());
+    }
+
+    #[test]
+    fn completes_common_prefix() {
+        let kinds = ["delimited", "string", "string_content"];
+        let mut prefix = "s".to_string();
+        assert_eq!(
+            complete_kind(&mut prefix, &kinds),
+            vec!["string", "string_content"]
+        );
+        assert_eq!(prefix, "string");
+    }
+}
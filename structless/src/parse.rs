@@ -0,0 +1,333 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tracing::debug;
+use tree_sitter::{Language, Parser, Tree};
+
+/// Share of the source that may be covered by errors before it is parsed with
+/// the structless grammar instead
+const ERROR_LIMIT: f64 = 0.5;
+
+/// Why a source was parsed with the structless grammar instead of the
+/// requested language
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fallback {
+    TimedOut,
+    /// This share of the source was covered by errors
+    Errors(f64),
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fallback::TimedOut => write!(f, "parsing timed out, using structless"),
+            Fallback::Errors(share) => write!(
+                f,
+                "{:.0}% of the text are errors, using structless",
+                share * 100.0
+            ),
+        }
+    }
+}
+
+/// State shared between a parse and the thread waiting for it
+#[derive(Debug, Default)]
+struct Shared {
+    /// Bytes of the source the parser has read so far
+    consumed: AtomicUsize,
+    /// Set once parsing with the requested language failed and the source is
+    /// parsed with the structless grammar instead
+    falling_back: Mutex<Option<Fallback>>,
+    /// Stops the parser when set to a value other than 0
+    cancelled: AtomicUsize,
+}
+
+/// Source and tree of a finished parse
+#[derive(Debug)]
+pub struct Parsed {
+    pub source: String,
+    pub tree: Tree,
+    /// Language that was requested, `tree` may be parsed with the structless
+    /// grammar instead
+    pub language: Language,
+    /// Why `tree` was parsed with the structless grammar instead of
+    /// `language`
+    pub fallback: Option<Fallback>,
+}
+
+/// A parse running on a worker thread
+#[derive(Debug)]
+pub struct Parsing {
+    shared: Arc<Shared>,
+    len: usize,
+    handle: JoinHandle<Result<Option<Parsed>>>,
+}
+
+impl Parsing {
+    /// Starts parsing `source` with `language`. If that takes longer than
+    /// `timeout`, or if `check_errors` and most of the tree are errors, the
+    /// source is parsed with the structless grammar instead.
+    pub fn start(
+        source: String,
+        language: Language,
+        timeout: Option<Duration>,
+        check_errors: bool,
+    ) -> Parsing {
+        Parsing::spawn(source, language, None, timeout, check_errors)
+    }
+
+    /// Like `start`, but parses the changed `source` of `old_tree` reusing
+    /// the unchanged parts. `old_tree` must already be edited to match
+    /// `source`.
+    pub fn reparse(
+        source: String,
+        old_tree: Tree,
+        timeout: Option<Duration>,
+        check_errors: bool,
+    ) -> Parsing {
+        let language = old_tree.language();
+        Parsing::spawn(source, language, Some(old_tree), timeout, check_errors)
+    }
+
+    fn spawn(
+        source: String,
+        language: Language,
+        old_tree: Option<Tree>,
+        timeout: Option<Duration>,
+        check_errors: bool,
+    ) -> Parsing {
+        let shared = Arc::new(Shared::default());
+        let len = source.len();
+        let handle = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let old_tree = old_tree.as_ref();
+                parse_with_fallback(source, language, old_tree, timeout, check_errors, &shared)
+            })
+        };
+        Parsing {
+            shared,
+            len,
+            handle,
+        }
+    }
+
+    /// Number of bytes parsed so far, and the size of the source
+    pub fn progress(&self) -> (usize, usize) {
+        let consumed = self.shared.consumed.load(Ordering::Relaxed);
+        (consumed.min(self.len), self.len)
+    }
+
+    /// Why the structless grammar is used instead of the requested language,
+    /// if it is
+    pub fn falling_back(&self) -> Option<Fallback> {
+        *self.shared.falling_back.lock().unwrap()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Stops the parser and waits for the worker thread to exit
+    pub fn cancel(self) {
+        self.shared.cancelled.store(1, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+
+    /// Waits for the parse to finish. Returns `None` if it was cancelled.
+    pub fn finish(self) -> Result<Option<Parsed>> {
+        self.handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("the parser thread panicked"))?
+    }
+}
+
+fn parse_with_fallback(
+    source: String,
+    language: Language,
+    old_tree: Option<&Tree>,
+    timeout: Option<Duration>,
+    check_errors: bool,
+    shared: &Shared,
+) -> Result<Option<Parsed>> {
+    let structless = tree_sitter_structless::language();
+    // there is nothing to fall back to for the structless grammar itself
+    if language == structless {
+        return Ok(
+            parse(&source, language, old_tree, None, shared)?.map(|tree| Parsed {
+                source,
+                tree,
+                language,
+                fallback: None,
+            }),
+        );
+    }
+
+    let fallback = match parse(&source, language, old_tree, timeout, shared)? {
+        Some(tree) => match error_fallback(&tree).filter(|_| check_errors) {
+            Some(fallback) => fallback,
+            None => {
+                return Ok(Some(Parsed {
+                    source,
+                    tree,
+                    language,
+                    fallback: None,
+                }))
+            }
+        },
+        None if shared.cancelled.load(Ordering::Relaxed) != 0 => return Ok(None),
+        None => Fallback::TimedOut,
+    };
+
+    debug!(?timeout, ?fallback, "falling back to structless");
+    shared.consumed.store(0, Ordering::Relaxed);
+    *shared.falling_back.lock().unwrap() = Some(fallback);
+    Ok(
+        parse(&source, structless, None, None, shared)?.map(|tree| Parsed {
+            source,
+            tree,
+            language,
+            fallback: Some(fallback),
+        }),
+    )
+}
+
+/// `Fallback::Errors` if most of `tree` are errors and it should be parsed
+/// with the structless grammar instead
+pub fn error_fallback(tree: &Tree) -> Option<Fallback> {
+    if tree.language() == tree_sitter_structless::language() {
+        return None;
+    }
+    let share = error_share(tree);
+    (share > ERROR_LIMIT).then_some(Fallback::Errors(share))
+}
+
+/// Share of the source that is covered by `ERROR` nodes
+pub fn error_share(tree: &Tree) -> f64 {
+    let root = tree.root_node();
+    if root.end_byte() == 0 {
+        return 0.0;
+    }
+    let mut errors = 0;
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() {
+            errors += node.end_byte() - node.start_byte();
+        } else if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return errors as f64 / root.end_byte() as f64;
+            }
+        }
+    }
+}
+
+/// Parses `source`, returning `None` if the parse timed out or was cancelled
+fn parse(
+    source: &str,
+    language: Language,
+    old_tree: Option<&Tree>,
+    timeout: Option<Duration>,
+    shared: &Shared,
+) -> Result<Option<Tree>> {
+    let mut parser = Parser::new();
+    parser.set_language(language)?;
+    if let Some(timeout) = timeout {
+        parser.set_timeout_micros(timeout.as_micros().max(1) as u64);
+    }
+    // SAFETY: `shared` outlives `parser`, which is dropped at the end of this
+    // function
+    unsafe { parser.set_cancellation_flag(Some(&shared.cancelled)) };
+
+    // hand out the source in chunks, so that every read tells how far the
+    // parser got
+    const CHUNK: usize = 64 * 1024;
+    let bytes = source.as_bytes();
+    Ok(parser.parse_with(
+        &mut |offset, _| {
+            shared.consumed.fetch_max(offset, Ordering::Relaxed);
+            let start = offset.min(bytes.len());
+            &bytes[start..(start + CHUNK).min(bytes.len())]
+        },
+        old_tree,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_structless_on_timeout() {
+        let source = "[1, ".repeat(50_000);
+        let parsing = Parsing::start(
+            source,
+            tree_sitter_json::language(),
+            Some(Duration::from_micros(1)),
+            false,
+        );
+        let parsed = parsing.finish().unwrap().unwrap();
+        assert_eq!(parsed.fallback, Some(Fallback::TimedOut));
+        assert_eq!(parsed.tree.language(), tree_sitter_structless::language());
+    }
+
+    #[test]
+    fn parses_with_requested_language() {
+        let parsing = Parsing::start("[1]".to_string(), tree_sitter_json::language(), None, true);
+        let parsed = parsing.finish().unwrap().unwrap();
+        assert_eq!(parsed.fallback, None);
+        assert_eq!(parsed.tree.root_node().kind(), "document");
+    }
+
+    #[test]
+    fn reparses_appended_text() {
+        let parsing = Parsing::start("[1".to_string(), tree_sitter_json::language(), None, false);
+        let mut old_tree = parsing.finish().unwrap().unwrap().tree;
+        old_tree.edit(&tree_sitter::InputEdit {
+            start_byte: 2,
+            old_end_byte: 2,
+            new_end_byte: 6,
+            start_position: tree_sitter::Point::new(0, 2),
+            old_end_position: tree_sitter::Point::new(0, 2),
+            new_end_position: tree_sitter::Point::new(0, 6),
+        });
+        let parsing = Parsing::reparse("[1, 2]".to_string(), old_tree, None, false);
+        let parsed = parsing.finish().unwrap().unwrap();
+        assert_eq!(parsed.fallback, None);
+        assert_eq!(
+            parsed.tree.root_node().to_sexp(),
+            "(document (array (number) (number)))"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_structless_on_errors() {
+        let source = "{\"a\": 1}\nstarted server on port 8080\nstopped server\n";
+        let parse = |check_errors| {
+            Parsing::start(
+                source.to_string(),
+                tree_sitter_json::language(),
+                None,
+                check_errors,
+            )
+            .finish()
+            .unwrap()
+            .unwrap()
+        };
+        let parsed = parse(true);
+        assert!(matches!(parsed.fallback, Some(Fallback::Errors(share)) if share > ERROR_LIMIT));
+        assert_eq!(parsed.tree.language(), tree_sitter_structless::language());
+        assert_eq!(parse(false).fallback, None);
+    }
+}
@@ -0,0 +1,433 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use tree_sitter::Node;
+
+use crate::lines;
+
+/// One step down the tree: the index of a child among all children of its
+/// parent, and the field name of the child, if it has one
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Step {
+    pub index: usize,
+    pub field: Option<Cow<'static, str>>,
+    /// Where the child started when the step was taken. Only used to find
+    /// the child quickly, the index decides which child it is.
+    #[serde(default)]
+    pub start: usize,
+}
+
+/// Where a node is in a tree, as the steps that lead to it from the root.
+///
+/// Unlike `Node::id`, a path still means something after the source was
+/// parsed again, so it can be kept across reloads, saved and shown to the
+/// user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodePath(pub Vec<Step>);
+
+impl NodePath {
+    /// Path from `root` to `node`
+    pub fn of(root: Node, node: Node) -> NodePath {
+        let mut nodes = lines::ancestors(root, node);
+        nodes.push(node);
+        let steps = nodes
+            .windows(2)
+            .map(|pair| {
+                let child = pair[1];
+                // the first child ending at or after the start of `child`,
+                // zero width children may follow a sibling that ends there
+                let mut cursor = pair[0].walk();
+                let mut index = cursor
+                    .goto_first_child_for_byte(child.start_byte())
+                    .expect("ancestors contain their descendants");
+                while cursor.node() != child && cursor.goto_next_sibling() {
+                    index += 1;
+                }
+                Step {
+                    index,
+                    field: cursor.field_name().map(Cow::Borrowed),
+                    start: child.start_byte(),
+                }
+            })
+            .collect();
+        NodePath(steps)
+    }
+
+    /// The node at this path below `root`, or the deepest node on the way
+    /// that the tree still has
+    pub fn resolve_nearest<'t>(&self, root: Node<'t>) -> Node<'t> {
+        let mut node = root;
+        for step in &self.0 {
+            match child_at(node, step) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        node
+    }
+}
+
+impl Display for NodePath {
+    /// Like `/0/value/3`, with field names where the children have one
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "/");
+        }
+        for step in &self.0 {
+            match &step.field {
+                Some(field) => write!(f, "/{}", field)?,
+                None => write!(f, "/{}", step.index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The child of `node` that `step` leads to. If the child at the index has a
+/// different field, e.g. because a sibling was inserted before it, the child
+/// with the field is taken instead.
+///
+/// The child is looked up at the position it had when the step was taken.
+/// Only if the text before it changed, tree-sitter has to count the
+/// children up to the index.
+fn child_at<'t>(node: Node<'t>, step: &Step) -> Option<Node<'t>> {
+    let mut cursor = node.walk();
+    if let Some(mut index) = cursor.goto_first_child_for_byte(step.start) {
+        while index < step.index
+            && cursor.goto_next_sibling()
+            && cursor.node().start_byte() <= step.start
+        {
+            index += 1;
+        }
+        if index == step.index && cursor.field_name() == step.field.as_deref() {
+            return Some(cursor.node());
+        }
+    }
+    match &step.field {
+        Some(field) => node.child_by_field_name(field.as_ref()),
+        None => node.child(step.index),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Index of the entry of the parent path, the root entry is its own
+    /// parent
+    parent: usize,
+    step: Step,
+    contains: bool,
+}
+
+/// A set of node paths, stored as a trie in a flat list. Paths that share a
+/// prefix share its entries, so a path and all of its ancestors take space
+/// linear in its length, even in deeply nested trees.
+#[derive(Debug, Clone)]
+pub struct PathSet {
+    /// Parents come before their children, the first entry is the root
+    entries: Vec<Entry>,
+    /// Entry of each parent entry and child index. Steps only differ in
+    /// their field name if the tree changed, so the index is enough.
+    children: HashMap<(usize, usize), usize>,
+}
+
+impl Default for PathSet {
+    fn default() -> PathSet {
+        PathSet {
+            entries: vec![Entry {
+                parent: 0,
+                step: Step {
+                    index: 0,
+                    field: None,
+                    start: 0,
+                },
+                contains: false,
+            }],
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl PathSet {
+    /// Set with the given entries, if every parent comes before its children
+    fn from_entries(entries: Vec<Entry>) -> Option<PathSet> {
+        let mut children = HashMap::new();
+        for (i, entry) in entries.iter().enumerate().skip(1) {
+            if entry.parent >= i {
+                return None;
+            }
+            children.insert((entry.parent, entry.step.index), i);
+        }
+        (!entries.is_empty()).then_some(PathSet { entries, children })
+    }
+
+    /// Index of the entry of the child of `parent` at `step`, created if
+    /// needed
+    fn child(&mut self, parent: usize, step: &Step) -> usize {
+        let entries = &mut self.entries;
+        let child = *self
+            .children
+            .entry((parent, step.index))
+            .or_insert_with(|| {
+                entries.push(Entry {
+                    parent,
+                    step: step.clone(),
+                    contains: false,
+                });
+                entries.len() - 1
+            });
+        if entries[child].step != *step {
+            entries[child].step = step.clone();
+        }
+        child
+    }
+
+    fn entry(&mut self, path: &NodePath) -> usize {
+        path.0.iter().fold(0, |entry, step| self.child(entry, step))
+    }
+
+    pub fn contains(&self, path: &NodePath) -> bool {
+        let mut entry = 0;
+        for step in &path.0 {
+            match self.children.get(&(entry, step.index)) {
+                Some(&child) => entry = child,
+                None => return false,
+            }
+        }
+        self.entries[entry].contains
+    }
+
+    /// Adds or removes `path`. Returns whether the set changed.
+    pub fn set(&mut self, path: &NodePath, contains: bool) -> bool {
+        if !contains && !self.contains(path) {
+            return false;
+        }
+        let entry = self.entry(path);
+        let changed = self.entries[entry].contains != contains;
+        self.entries[entry].contains = contains;
+        changed
+    }
+
+    /// Adds all ancestors of `path`, but not `path` itself
+    pub fn insert_ancestors(&mut self, path: &NodePath) {
+        let mut entry = 0;
+        for step in &path.0 {
+            self.entries[entry].contains = true;
+            entry = self.child(entry, step);
+        }
+    }
+
+    /// Adds or removes the paths of all nodes below `node`, which is at
+    /// `path`. Nodes without children are left out when adding, there is
+    /// nothing to expand.
+    pub fn set_below(&mut self, path: &NodePath, node: Node, contains: bool) {
+        if !contains {
+            let base = match self.find(path) {
+                Some(base) => base,
+                None => return,
+            };
+            // parents come first, so one pass finds all entries below `base`
+            let mut below = vec![false; self.entries.len()];
+            for i in 1..self.entries.len() {
+                let parent = self.entries[i].parent;
+                below[i] = parent == base || below[parent];
+                if below[i] {
+                    self.entries[i].contains = false;
+                }
+            }
+            return;
+        }
+
+        // entry of the parent and index of the current node, per level
+        let mut levels = vec![(self.entry(path), 0)];
+        let mut cursor = node.walk();
+        if !cursor.goto_first_child() {
+            return;
+        }
+        loop {
+            let (parent, index) = *levels.last().unwrap();
+            if cursor.node().child_count() > 0 {
+                let step = Step {
+                    index,
+                    field: cursor.field_name().map(Cow::Borrowed),
+                    start: cursor.node().start_byte(),
+                };
+                let entry = self.child(parent, &step);
+                self.entries[entry].contains = true;
+                cursor.goto_first_child();
+                levels.push((entry, 0));
+                continue;
+            }
+            loop {
+                if cursor.goto_next_sibling() {
+                    levels.last_mut().unwrap().1 += 1;
+                    break;
+                }
+                if levels.len() == 1 {
+                    return;
+                }
+                cursor.goto_parent();
+                levels.pop();
+            }
+        }
+    }
+
+    fn find(&self, path: &NodePath) -> Option<usize> {
+        path.0.iter().try_fold(0, |entry, step| {
+            self.children.get(&(entry, step.index)).copied()
+        })
+    }
+
+    /// Ids of the nodes below `root` whose paths are in the set. Only the
+    /// nodes at the entries are visited, not their siblings.
+    pub fn ids(&self, root: Node) -> HashSet<usize> {
+        let mut children = vec![Vec::new(); self.entries.len()];
+        for (i, entry) in self.entries.iter().enumerate().skip(1) {
+            children[entry.parent].push(i);
+        }
+
+        let mut ids = HashSet::new();
+        let mut pending = vec![(0, root)];
+        while let Some((entry, node)) = pending.pop() {
+            if self.entries[entry].contains {
+                ids.insert(node.id());
+            }
+            for child in std::mem::take(&mut children[entry]) {
+                if let Some(found) = child_at(node, &self.entries[child].step) {
+                    pending.push((child, found));
+                }
+            }
+        }
+        ids
+    }
+}
+
+/// Only the entries that lead to a path in the set are saved
+impl Serialize for PathSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // children come after their parents, so walking backwards sees all
+        // of them before the parent
+        let mut needed: Vec<_> = self.entries.iter().map(|entry| entry.contains).collect();
+        for i in (1..self.entries.len()).rev() {
+            needed[self.entries[i].parent] |= needed[i];
+        }
+        needed[0] = true;
+
+        let mut new_index = vec![0; self.entries.len()];
+        let mut entries = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if needed[i] {
+                new_index[i] = entries.len();
+                entries.push(Entry {
+                    parent: new_index[entry.parent],
+                    ..entry.clone()
+                });
+            }
+        }
+        entries.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PathSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PathSet, D::Error> {
+        let entries = Vec::<Entry>::deserialize(deserializer)?;
+        PathSet::from_entries(entries)
+            .ok_or_else(|| D::Error::custom("parents have to come before their children"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn parse(source: &str, language: tree_sitter::Language) -> tree_sitter::Tree {
+        let mut parser = Parser::new();
+        parser.set_language(language).unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    #[test]
+    fn resolves_paths_in_new_trees() {
+        let old = parse("[1, [2, 3]]", tree_sitter_json::language());
+        let three = old.root_node().descendant_for_byte_range(8, 9).unwrap();
+        let path = NodePath::of(old.root_node(), three);
+        assert_eq!(path.to_string(), "/0/3/3");
+        assert_eq!(path.resolve_nearest(old.root_node()), three);
+
+        let new = parse("[1, [2, 4], 5]", tree_sitter_json::language());
+        assert_eq!(path.resolve_nearest(new.root_node()).byte_range(), 8..9);
+        // the text before the node changed, so it starts somewhere else
+        let moved = parse("[10, [2, 3]]", tree_sitter_json::language());
+        assert_eq!(path.resolve_nearest(moved.root_node()).byte_range(), 9..10);
+        let shorter = parse("[1, [2]]", tree_sitter_json::language());
+        assert_eq!(path.resolve_nearest(shorter.root_node()).byte_range(), 4..7);
+    }
+
+    #[test]
+    fn follows_field_names() {
+        let old = parse("fn f() {}", tree_sitter_rust::language());
+        let body = old.root_node().child(0).unwrap().child(3).unwrap();
+        let path = NodePath::of(old.root_node(), body);
+        assert_eq!(path.to_string(), "/0/body");
+
+        // the body moved to another index
+        let new = parse("fn f() -> u8 {}", tree_sitter_rust::language());
+        assert_eq!(path.resolve_nearest(new.root_node()).kind(), "block");
+    }
+
+    #[test]
+    fn sets_resolve_to_ids() {
+        let tree = parse("[[1], [2, [3]]]", tree_sitter_json::language());
+        let root = tree.root_node();
+        let outer = root.child(0).unwrap();
+        let second = outer.named_child(1).unwrap();
+        let innermost = second.named_child(1).unwrap();
+
+        let mut set = PathSet::default();
+        set.insert_ancestors(&NodePath::of(root, innermost));
+        assert_eq!(
+            set.ids(root),
+            HashSet::from([root.id(), outer.id(), second.id()])
+        );
+        assert!(set.set(&NodePath::of(root, outer), false));
+        assert!(!set.set(&NodePath::of(root, outer), false));
+        assert_eq!(set.ids(root), HashSet::from([root.id(), second.id()]));
+
+        set.set_below(&NodePath::default(), root, false);
+        assert_eq!(set.ids(root), HashSet::from([root.id()]));
+        set.set_below(&NodePath::of(root, second), second, true);
+        assert!(set.contains(&NodePath::of(root, innermost)));
+        assert!(!set.contains(&NodePath::of(root, second)));
+        let parents = lines::descendants(&second)
+            .iter()
+            .filter(|node| node.child_count() > 0)
+            .count();
+        assert_eq!(set.ids(root).len(), 1 + parents);
+    }
+
+    #[test]
+    fn saves_only_needed_entries() {
+        let tree = parse("[[1], [2, [3]]]", tree_sitter_json::language());
+        let root = tree.root_node();
+        let outer = root.child(0).unwrap();
+        let first = outer.named_child(0).unwrap();
+        let innermost = outer.named_child(1).unwrap().named_child(1).unwrap();
+
+        let mut set = PathSet::default();
+        set.set(&NodePath::of(root, first), true);
+        set.set(&NodePath::of(root, innermost), true);
+        set.set(&NodePath::of(root, first), false);
+        let json = serde_json::to_string(&set).unwrap();
+        let loaded: PathSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.entries.len(), 4);
+        assert_eq!(loaded.ids(root), HashSet::from([innermost.id()]));
+
+        let invalid = r#"[{"parent": 0, "step": {"index": 0, "field": null}, "contains": false},
+            {"parent": 1, "step": {"index": 0, "field": null}, "contains": true}]"#;
+        assert!(serde_json::from_str::<PathSet>(invalid).is_err());
+    }
+}
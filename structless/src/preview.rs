@@ -0,0 +1,307 @@
+use std::ops::Range;
+
+use tracing::{instrument, trace};
+use tree_sitter::Node;
+use tui::{
+    style::{Color, Style},
+    text::Span,
+};
+use unicode_width::UnicodeWidthChar;
+
+use crate::search::Search;
+
+/// Single line preview of `source[range]` that is at most `max_width`
+/// columns wide. Whitespace containing a line break is shown as `⏎`, other
+/// runs of whitespace are collapsed into one space, and a cut off preview
+/// ends in `…`. Every char comes with the offset in `source` it stands for.
+///
+/// Only the part of the source that fits is looked at, so this is cheap even
+/// for huge nodes.
+pub fn compact(source: &str, range: Range<usize>, max_width: usize) -> Vec<(char, usize)> {
+    let mut preview = Vec::new();
+    let mut width = 0;
+    let mut chars = source[range.clone()].char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let (shown, offset) = if c.is_whitespace() {
+            let mut line_break = (c == '\n').then_some(offset);
+            while let Some(&(next_offset, next)) = chars.peek() {
+                if !next.is_whitespace() {
+                    break;
+                }
+                if next == '\n' && line_break.is_none() {
+                    line_break = Some(next_offset);
+                }
+                chars.next();
+            }
+            match line_break {
+                Some(line_break) => ('⏎', line_break),
+                None => (' ', offset),
+            }
+        } else {
+            (c, offset)
+        };
+
+        let shown_width = shown.width().unwrap_or(0);
+        // keep one column for the ellipsis, unless this is the last char
+        let reserved = if chars.peek().is_some() { 1 } else { 0 };
+        if width + shown_width + reserved > max_width {
+            if width < max_width {
+                preview.push(('…', range.end));
+            }
+            break;
+        }
+        width += shown_width;
+        preview.push((shown, range.start + offset));
+    }
+    preview
+}
+
+/// Turns a preview from `compact` into spans, highlighting chars that stand
+/// for a search match
+pub fn highlight(
+    preview: &[(char, usize)],
+    range: Range<usize>,
+    search: &Search,
+    style: Style,
+) -> Vec<Span<'static>> {
+    let matches: Vec<_> = search.matches_in(range).collect();
+    let mut next_match = 0;
+    let mut spans = Vec::new();
+    let mut text = String::new();
+    let mut current_style = style;
+
+    for &(c, offset) in preview {
+        while next_match < matches.len() && matches[next_match].0.end <= offset {
+            next_match += 1;
+        }
+        let char_style = match matches.get(next_match) {
+            Some((m, is_current)) if m.contains(&offset) => {
+                if *is_current {
+                    style.fg(Color::Black).bg(Color::LightRed)
+                } else {
+                    style.fg(Color::Black).bg(Color::Yellow)
+                }
+            }
+            _ => style,
+        };
+        if char_style != current_style && !text.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut text), current_style));
+        }
+        current_style = char_style;
+        text.push(c);
+    }
+    if !text.is_empty() {
+        spans.push(Span::styled(text, current_style));
+    }
+    spans
+}
+
+/// Opening and closing text of a node, e.g. `fn main() {` and `}`, and what
+/// lies between them
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fold {
+    pub header: Range<usize>,
+    pub footer: Range<usize>,
+    /// Number of named nodes between header and footer
+    pub children: usize,
+    /// What the children are called, e.g. "keys" for the pairs of an object
+    pub unit: &'static str,
+}
+
+impl Fold {
+    pub fn new(node: &Node, source: &str) -> Option<Fold> {
+        let delimited = find_delimited(*node)?;
+        let inner = get_smaller_child_range(node)?;
+
+        // only the first children are looked at, so that this stays cheap
+        // for nodes with many children. Some grammars name the delimiters,
+        // like `bracket_start`, those are no children.
+        let first = delimited.child(0)?;
+        let last = delimited.child(delimited.child_count() - 1)?;
+        let named_delimiters = [first, last]
+            .iter()
+            .filter(|&&d| d.is_named() && is_delimiter(d))
+            .count();
+        let children = delimited.named_child_count() - named_delimiters;
+        let pairs = delimited
+            .named_child((first.is_named() && is_delimiter(first)) as usize)
+            .is_some_and(|child| child.kind() == "pair");
+        let opening = &source[first.byte_range()];
+        let unit = match (children == 1, pairs, opening) {
+            (true, true, _) => "key",
+            (false, true, _) => "keys",
+            (true, false, "[") => "item",
+            (false, false, "[") => "items",
+            (true, false, _) => "child",
+            (false, false, _) => "children",
+        };
+
+        let header = &source[node.start_byte()..inner.start_byte];
+        let footer = &source[inner.end_byte..node.end_byte()];
+        Some(Fold {
+            header: node.start_byte()..node.start_byte() + header.trim_end().len(),
+            footer: node.end_byte() - footer.trim_start().len()..node.end_byte(),
+            children,
+            unit,
+        })
+    }
+
+    /// Summary shown between header and footer of a collapsed node
+    pub fn summary(&self) -> String {
+        format!(" … {} {} … ", self.children, self.unit)
+    }
+}
+
+/// Finds the node whose first and last children are opening and closing
+/// delimiters like `{` and `}`. That is `node` itself, or the node it ends
+/// with if that starts on the same line, like the body of `fn main() {`.
+fn find_delimited(mut node: Node) -> Option<Node> {
+    loop {
+        let count = node.child_count();
+        if count == 0 {
+            return None;
+        }
+        let first = node.child(0).unwrap();
+        let last = node.child(count - 1).unwrap();
+
+        if count >= 2 && is_delimiter(first) && is_delimiter(last) {
+            return Some(node);
+        } else if last.start_position().row == node.start_position().row {
+            node = last;
+        } else {
+            return None;
+        }
+    }
+}
+
+/// Whether `node` is an opening or closing delimiter, like `{` or
+/// `bracket_start`
+fn is_delimiter(node: Node) -> bool {
+    let kind = node.kind();
+    node.child_count() == 0
+        && node.start_byte() < node.end_byte()
+        && (kind.chars().all(|c| c.is_ascii_punctuation())
+            || kind.ends_with("_start")
+            || kind.ends_with("_end"))
+}
+
+/// Gets the range between the opening and closing delimiters of `node`,
+/// see `find_delimited`
+#[instrument(fields(start=node.start_byte(), end=node.end_byte(), id=node.id()))]
+pub fn get_smaller_child_range(node: &Node) -> Option<tree_sitter::Range> {
+    let delimited = find_delimited(*node)?;
+    let count = delimited.child_count();
+    let child_start = delimited.child(0).unwrap();
+    let child_end = delimited.child(count - 1).unwrap();
+    trace!(?child_start, ?child_end, "delimiters");
+
+    Some(tree_sitter::Range {
+        start_byte: child_start.end_byte(),
+        start_point: child_start.end_position(),
+        end_byte: child_end.start_byte(),
+        end_point: child_end.start_position(),
+    })
+}
+
+/// Number of lines `node` spans. A trailing line break does not start a new
+/// line.
+pub fn row_count(node: &Node) -> usize {
+    let start = node.start_position();
+    let end = node.end_position();
+    if end.column == 0 && end.row > start.row {
+        end.row - start.row
+    } else {
+        end.row - start.row + 1
+    }
+}
+
+/// Size like "1.2 KB"
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Size hint like "(1.2 KB, 48 lines)"
+pub fn size_hint(bytes: usize, lines: usize) -> String {
+    let size = format_size(bytes);
+    if lines > 1 {
+        format!("({}, {} lines)", size, lines)
+    } else {
+        format!("({})", size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(preview: &[(char, usize)]) -> String {
+        preview.iter().map(|(c, _)| c).collect()
+    }
+
+    #[test]
+    fn collapses_whitespace() {
+        let source = "{\n  \"a\":   1\n}";
+        let preview = compact(source, 0..source.len(), 80);
+        assert_eq!(shown(&preview), "{⏎\"a\": 1⏎}");
+        assert_eq!(preview[1], ('⏎', 1));
+    }
+
+    #[test]
+    fn truncates_to_width() {
+        assert_eq!(shown(&compact("abcdef", 0..6, 4)), "abc…");
+        assert_eq!(shown(&compact("abcd", 0..4, 4)), "abcd");
+        assert_eq!(shown(&compact("日本語", 0..9, 5)), "日本…");
+    }
+
+    #[test]
+    fn folds_delimited_nodes() {
+        let source = "[{\"a\": 1, \"b\": [2, 3]}]";
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(tree_sitter_json::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let array = tree.root_node().child(0).unwrap();
+
+        let fold = Fold::new(&array, source).unwrap();
+        assert_eq!(&source[fold.header.clone()], "[");
+        assert_eq!(&source[fold.footer.clone()], "]");
+        assert_eq!(fold.summary(), " … 1 item … ");
+
+        let object = array.named_child(0).unwrap();
+        let fold = Fold::new(&object, source).unwrap();
+        assert_eq!(fold.summary(), " … 2 keys … ");
+
+        let pair = object.named_child(1).unwrap();
+        let fold = Fold::new(&pair, source).unwrap();
+        assert_eq!(&source[fold.header.clone()], "\"b\": [");
+        assert_eq!(fold.summary(), " … 2 items … ");
+
+        // the structless grammar names its delimiters
+        let source = "[\"a\" \"b\" \"c\"]";
+        parser
+            .set_language(tree_sitter_structless::language())
+            .unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        // `(top (delimited (bracket_start) (top …)×5 (bracket_end)))`
+        let top = tree.root_node().child(0).unwrap();
+        let fold = Fold::new(&top, source).unwrap();
+        assert_eq!(fold.summary(), " … 5 items … ");
+    }
+
+    #[test]
+    fn formats_size() {
+        assert_eq!(size_hint(12, 1), "(12 B)");
+        assert_eq!(size_hint(1229, 48), "(1.2 KB, 48 lines)");
+    }
+}
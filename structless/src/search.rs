@@ -0,0 +1,405 @@
+use std::{fmt::Display, ops::Range};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Query, QueryCursor, Tree};
+
+/// How the case of the search term is treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    /// Case insensitive, unless the term contains an upper case letter
+    Smart,
+}
+
+impl CaseMode {
+    pub fn cycle(self) -> CaseMode {
+        match self {
+            CaseMode::Sensitive => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Smart,
+            CaseMode::Smart => CaseMode::Sensitive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchOptions {
+    /// Interpret the term as a tree-sitter query and jump between its captures
+    pub query: bool,
+    /// Interpret the term as a regular expression instead of plain text
+    pub regex: bool,
+    pub case: CaseMode,
+    /// Only match if the term is surrounded by word boundaries
+    pub whole_word: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            query: false,
+            regex: false,
+            case: CaseMode::Sensitive,
+            whole_word: false,
+        }
+    }
+}
+
+impl SearchOptions {
+    fn build(&self, term: &str) -> Result<Regex, regex::Error> {
+        let pattern = if self.regex {
+            term.to_string()
+        } else {
+            regex::escape(term)
+        };
+        let pattern = if self.whole_word {
+            format!(r"\b(?:{})\b", pattern)
+        } else {
+            pattern
+        };
+        let case_insensitive = match self.case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !term.chars().any(char::is_uppercase),
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+    }
+}
+
+impl Display for SearchOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.query {
+            return write!(f, "query");
+        }
+        let case = match self.case {
+            CaseMode::Sensitive => "case",
+            CaseMode::Insensitive => "nocase",
+            CaseMode::Smart => "smartcase",
+        };
+        write!(
+            f,
+            "{}, {}{}",
+            if self.regex { "regex" } else { "text" },
+            case,
+            if self.whole_word { ", word" } else { "" }
+        )
+    }
+}
+
+/// Result of searching the whole source for a term, independent of which
+/// nodes are currently collapsed
+#[derive(Debug, Default)]
+pub struct Search {
+    /// Byte ranges of all occurrences, in document order
+    pub matches: Vec<Range<usize>>,
+    /// Index into `matches` of the match that was jumped to last
+    pub current: Option<usize>,
+    /// For query searches, the capture name of each match
+    pub captures: Vec<String>,
+    /// Largest end of each match and all matches before it. Query captures
+    /// nest, so `matches` are ordered by start only.
+    reach: Vec<usize>,
+}
+
+impl Search {
+    pub fn new(
+        term: &str,
+        source: &str,
+        tree: &Tree,
+        options: &SearchOptions,
+    ) -> anyhow::Result<Search> {
+        if term.is_empty() {
+            Ok(Search::default())
+        } else if options.query {
+            Search::query(term, source, tree)
+        } else {
+            let matches = options
+                .build(term)?
+                .find_iter(source)
+                .filter(|m| !m.range().is_empty())
+                .map(|m| m.range())
+                .collect();
+            Ok(Search::with_matches(matches, Vec::new()))
+        }
+    }
+
+    /// Runs `term` as a tree-sitter query against `tree`. Every capture
+    /// becomes a match.
+    fn query(term: &str, source: &str, tree: &Tree) -> anyhow::Result<Search> {
+        let query =
+            Query::new(tree.language(), term).map_err(|error| anyhow::anyhow!("{}", error))?;
+        let mut cursor = QueryCursor::new();
+
+        let mut captures: Vec<_> = cursor
+            .captures(&query, tree.root_node(), source.as_bytes())
+            .map(|(m, index)| {
+                let capture = m.captures[index];
+                (capture.node.byte_range(), capture.index)
+            })
+            .collect();
+        captures.sort_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+        captures.dedup_by_key(|(range, _)| range.clone());
+
+        let names = query.capture_names();
+        Ok(Search::with_matches(
+            captures.iter().map(|(range, _)| range.clone()).collect(),
+            captures
+                .iter()
+                .map(|(_, index)| names[*index as usize].clone())
+                .collect(),
+        ))
+    }
+
+    fn with_matches(matches: Vec<Range<usize>>, captures: Vec<String>) -> Search {
+        let reach = matches
+            .iter()
+            .scan(0, |reach, m| {
+                *reach = m.end.max(*reach);
+                Some(*reach)
+            })
+            .collect();
+        Search {
+            matches,
+            current: None,
+            captures,
+            reach,
+        }
+    }
+
+    /// Moves to the first match and returns its range
+    pub fn first(&mut self) -> Option<Range<usize>> {
+        self.select(0)
+    }
+
+    /// Moves to the next match, wrapping around at the end of the file
+    pub fn next(&mut self) -> Option<Range<usize>> {
+        match self.current {
+            Some(current) => self.select((current + 1) % self.matches.len().max(1)),
+            None => self.first(),
+        }
+    }
+
+    /// Moves to the previous match, wrapping around at the start of the file
+    pub fn previous(&mut self) -> Option<Range<usize>> {
+        let len = self.matches.len().max(1);
+        match self.current {
+            Some(current) => self.select((current + len - 1) % len),
+            None => self.select(len - 1),
+        }
+    }
+
+    fn select(&mut self, index: usize) -> Option<Range<usize>> {
+        let range = self.matches.get(index)?.clone();
+        self.current = Some(index);
+        Some(range)
+    }
+
+    /// The parts of `range` covered by matches, in order and without
+    /// overlaps. The flag tells if the part belongs to the current match,
+    /// which is shown over the matches that contain it.
+    pub fn matches_in(&self, range: Range<usize>) -> impl Iterator<Item = (Range<usize>, bool)> {
+        let clip = |m: &Range<usize>| m.start.max(range.start)..m.end.min(range.end);
+        let current = self
+            .current
+            .and_then(|current| self.matches.get(current))
+            .map(clip)
+            .filter(|current| !current.is_empty());
+
+        let first = self.reach.partition_point(|&end| end <= range.start);
+        let mut parts = Vec::new();
+        let mut position = range.start;
+        for m in self.matches[first..]
+            .iter()
+            .take_while(|m| m.start < range.end)
+        {
+            let part = m.start.max(position)..m.end.min(range.end);
+            if part.is_empty() {
+                continue;
+            }
+            position = part.end;
+            match &current {
+                Some(current) => {
+                    if part.start < current.start {
+                        parts.push((part.start..part.end.min(current.start), false));
+                    }
+                    if current.end < part.end {
+                        parts.push((part.start.max(current.end)..part.end, false));
+                    }
+                }
+                None => parts.push((part, false)),
+            }
+        }
+        if let Some(current) = current {
+            parts.push((current, true));
+            parts.sort_by_key(|(part, _)| part.start);
+        }
+        parts.into_iter()
+    }
+
+    /// Match counter as shown in the status line, e.g. "3/17", followed by
+    /// the capture name for query searches
+    pub fn status(&self) -> String {
+        match self.current {
+            Some(current) => match self.captures.get(current) {
+                Some(capture) => format!("{}/{} @{}", current + 1, self.matches.len(), capture),
+                None => format!("{}/{}", current + 1, self.matches.len()),
+            },
+            None => format!("-/{}", self.matches.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn parse(source: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser
+            .set_language(tree_sitter_structless::language())
+            .unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    fn search(term: &str, source: &str, options: SearchOptions) -> Vec<Range<usize>> {
+        Search::new(term, source, &parse(source), &options)
+            .unwrap()
+            .matches
+    }
+
+    #[test]
+    fn cycles_through_matches() {
+        let mut search = Search::new(
+            "ab",
+            "ab cd ab ef ab",
+            &parse(""),
+            &SearchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(search.matches, vec![0..2, 6..8, 12..14]);
+        assert_eq!(search.first(), Some(0..2));
+        assert_eq!(search.previous(), Some(12..14));
+        assert_eq!(search.next(), Some(0..2));
+        assert_eq!(search.next(), Some(6..8));
+        assert_eq!(search.status(), "2/3");
+    }
+
+    #[test]
+    fn empty_term_has_no_matches() {
+        let mut search = Search::new("", "abc", &parse(""), &SearchOptions::default()).unwrap();
+        assert_eq!(search.next(), None);
+        assert_eq!(search.status(), "-/0");
+    }
+
+    #[test]
+    fn plain_text_is_escaped() {
+        assert_eq!(search("a.", "ab a.", SearchOptions::default()), vec![3..5]);
+    }
+
+    #[test]
+    fn smart_case() {
+        let options = SearchOptions {
+            case: CaseMode::Smart,
+            ..SearchOptions::default()
+        };
+        assert_eq!(search("id", "Id id", options.clone()), vec![0..2, 3..5]);
+        assert_eq!(search("Id", "Id id", options), vec![0..2]);
+    }
+
+    #[test]
+    fn regex_whole_word() {
+        let options = SearchOptions {
+            regex: true,
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(search("i[a-z]", "id ids in", options), vec![0..2, 7..9]);
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let options = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        assert!(Search::new("(", "", &parse(""), &options).is_err());
+    }
+
+    #[test]
+    fn query_captures() {
+        let source = r#"{"a" ["b"]}"#;
+        let options = SearchOptions {
+            query: true,
+            ..SearchOptions::default()
+        };
+        let mut search = Search::new(
+            "(string (string_content) @s)",
+            source,
+            &parse(source),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(search.matches, vec![2..3, 7..8]);
+        search.next();
+        assert_eq!(search.status(), "1/2 @s");
+    }
+
+    #[test]
+    fn invalid_query() {
+        let options = SearchOptions {
+            query: true,
+            ..SearchOptions::default()
+        };
+        assert!(Search::new("(no_such_node)", "", &parse(""), &options).is_err());
+    }
+
+    #[test]
+    fn clips_matches_to_range() {
+        let mut search =
+            Search::new("ab", "abab ab", &parse(""), &SearchOptions::default()).unwrap();
+        search.next();
+        let clipped: Vec<_> = search.matches_in(1..6).collect();
+        assert_eq!(clipped, vec![(1..2, true), (2..4, false), (5..6, false)]);
+    }
+
+    #[test]
+    fn splits_nested_captures() {
+        let source = r#"{"a" "bc"}"#;
+        let options = SearchOptions {
+            query: true,
+            ..SearchOptions::default()
+        };
+        let mut search = Search::new(
+            "(top (string) @s) (string_content) @c",
+            source,
+            &parse(source),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(search.matches, vec![1..4, 2..3, 5..9, 6..8]);
+        // the captures inside strings still show up after the strings
+        let parts: Vec<_> = search.matches_in(3..10).collect();
+        assert_eq!(parts, vec![(3..4, false), (5..9, false)]);
+
+        // between the strings, only the capture around them is left
+        let outer = Search::new(
+            "(delimited) @d (string) @s",
+            source,
+            &parse(source),
+            &options,
+        );
+        let parts: Vec<_> = outer.unwrap().matches_in(4..5).collect();
+        assert_eq!(parts, vec![(4..5, false)]);
+
+        search.next();
+        search.next();
+        let parts: Vec<_> = search.matches_in(0..10).collect();
+        assert_eq!(
+            parts,
+            vec![(1..2, false), (2..3, true), (3..4, false), (5..9, false)]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    language::LanguageArgument,
+    path::{NodePath, PathSet},
+    search::SearchOptions,
+    ViewMode,
+};
+
+/// State of the viewer for a file, saved when it is closed and restored when
+/// it is opened again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    /// Canonical path of the file
+    pub path: PathBuf,
+    /// See `content_hash`. If the file changed since, the paths below may
+    /// point to different nodes than before.
+    pub content_hash: u64,
+    /// Grammar of the tree that the paths below belong to
+    pub language: LanguageArgument,
+    pub folds: PathSet,
+    pub selected: NodePath,
+    pub marks: HashMap<char, NodePath>,
+    /// First visible line of the tree view
+    pub tree_offset: usize,
+    pub text_scroll: usize,
+    pub search_term: String,
+    /// How `search_term` is interpreted, e.g. as a tree-sitter query
+    pub search_options: SearchOptions,
+    pub view_mode: ViewMode,
+}
+
+impl Session {
+    /// The session saved for the file at `path`. A missing or unreadable
+    /// session is no error, there is simply nothing to restore.
+    pub fn load(path: &Path) -> Option<Session> {
+        let canonical = fs::canonicalize(path).ok()?;
+        let file = state_file(&canonical)?;
+        let session = fs::read(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(serde_json::from_slice::<Session>(&json)?));
+        match session {
+            Ok(session) if session.path == canonical => Some(session),
+            Ok(_) => None,
+            Err(error) => {
+                debug!(?file, %error, "can't read the session");
+                None
+            }
+        }
+    }
+
+    /// Saves the session, replacing an older one of the same file
+    pub fn save(&self) -> anyhow::Result<()> {
+        let file = state_file(&self.path)
+            .ok_or_else(|| anyhow::anyhow!("neither XDG_DATA_HOME nor HOME is set"))?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first, so that a crash can't leave a
+        // half written session behind
+        let temporary = file.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(self)?)?;
+        fs::rename(&temporary, &file)?;
+        Ok(())
+    }
+}
+
+/// Where the session of the file at the canonical path `path` is saved,
+/// below `$XDG_DATA_HOME/structless/sessions`
+fn state_file(path: &Path) -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    let name = format!("{:016x}.json", fnv1a(path.to_string_lossy().as_bytes()));
+    Some(data_home.join("structless/sessions").join(name))
+}
+
+/// Hash of the content of a file, to tell if it changed between sessions.
+/// Unlike `DefaultHasher`, it is the same for every build.
+pub fn content_hash(source: &str) -> u64 {
+    fnv1a(source.as_bytes())
+}
+
+/// 64 bit FNV-1a hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn sessions_round_trip() {
+        let session = Session {
+            path: PathBuf::from("/tmp/a.json"),
+            content_hash: content_hash("[1]"),
+            language: LanguageArgument::Unknown,
+            folds: PathSet::default(),
+            selected: NodePath::default(),
+            marks: HashMap::from([('a', NodePath::default())]),
+            tree_offset: 3,
+            text_scroll: 4,
+            search_term: "x".to_string(),
+            search_options: SearchOptions {
+                regex: true,
+                ..SearchOptions::default()
+            },
+            view_mode: ViewMode::Split,
+        };
+        let json = serde_json::to_string(&session).unwrap();
+        let loaded: Session = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.path, session.path);
+        assert_eq!(loaded.content_hash, session.content_hash);
+        assert_eq!(loaded.language, LanguageArgument::Unknown);
+        assert_eq!(loaded.marks, session.marks);
+        assert_eq!(loaded.view_mode, ViewMode::Split);
+        assert!(loaded.search_options.regex);
+    }
+}
@@ -0,0 +1,152 @@
+use std::ops::Range;
+
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+};
+
+use crate::search::Search;
+
+/// Byte ranges of the lines in `source[range]`, without the line breaks
+pub fn line_ranges(source: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = range.start;
+    for (offset, _) in source[range.clone()].match_indices('\n') {
+        let end = range.start + offset;
+        lines.push(start..end);
+        start = end + 1;
+    }
+    if start < range.end || lines.is_empty() {
+        lines.push(start..range.end);
+    }
+    lines
+        .into_iter()
+        .map(|line| {
+            if source[line.clone()].ends_with('\r') {
+                line.start..line.end - 1
+            } else {
+                line
+            }
+        })
+        .collect()
+}
+
+/// Index of the line in `lines` that contains `byte`. A byte of a line break
+/// belongs to the line that it ends.
+pub fn line_of_byte(lines: &[Range<usize>], byte: usize) -> usize {
+    lines
+        .partition_point(|line| line.start <= byte)
+        .saturating_sub(1)
+}
+
+/// Splits `source[range]` into spans, highlighting the parts covered by a
+/// search match
+pub fn highlight_matches(
+    source: &str,
+    range: Range<usize>,
+    search: &Search,
+    style: Style,
+) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut position = range.start;
+    for (m, is_current) in search.matches_in(range.clone()) {
+        if m.start > position {
+            spans.push(Span::styled(source[position..m.start].to_string(), style));
+        }
+        let match_style = if is_current {
+            style.fg(Color::Black).bg(Color::LightRed)
+        } else {
+            style.fg(Color::Black).bg(Color::Yellow)
+        };
+        spans.push(Span::styled(source[m.clone()].to_string(), match_style));
+        position = m.end;
+    }
+    if position < range.end {
+        spans.push(Span::styled(source[position..range.end].to_string(), style));
+    }
+    spans
+}
+
+/// Renders `lines`, cut to `bounds`, starting at `scroll` with a gutter of
+/// line numbers. `first_row` is the row of the first line in the file,
+/// `hscroll` the
+/// number of characters cut off at the start of each line. Text outside of
+/// `focus` is dimmed.
+#[allow(clippy::too_many_arguments)]
+pub fn numbered_lines(
+    source: &str,
+    lines: &[Range<usize>],
+    bounds: Range<usize>,
+    first_row: usize,
+    scroll: usize,
+    hscroll: usize,
+    height: usize,
+    focus: Option<Range<usize>>,
+    search: &Search,
+) -> Vec<Spans<'static>> {
+    let dimmed = Style::default()
+        .fg(Color::DarkGray)
+        .add_modifier(Modifier::DIM);
+    let gutter_width = (first_row + lines.len()).to_string().len();
+    lines
+        .iter()
+        .enumerate()
+        .skip(scroll)
+        .take(height)
+        .map(|(i, line)| {
+            let start = line.start.max(bounds.start);
+            let line = &(start..line.end.min(bounds.end).max(start));
+            let visible_start = source[line.clone()]
+                .char_indices()
+                .nth(hscroll)
+                .map_or(line.end, |(offset, _)| line.start + offset);
+
+            let focus = focus.clone().unwrap_or(line.clone());
+            let focus_start = focus.start.clamp(visible_start, line.end);
+            let focus_end = focus.end.clamp(focus_start, line.end);
+
+            let in_focus = line.start < focus.end && focus.start <= line.end;
+            let mut spans = vec![Span::styled(
+                format!("{:>width$} ", first_row + i + 1, width = gutter_width),
+                if in_focus {
+                    Style::default().fg(Color::Gray)
+                } else {
+                    dimmed
+                },
+            )];
+            for (segment, style) in [
+                (visible_start..focus_start, dimmed),
+                (focus_start..focus_end, Style::default()),
+                (focus_end..line.end, dimmed),
+            ] {
+                if !segment.is_empty() {
+                    spans.append(&mut highlight_matches(source, segment, search, style));
+                }
+            }
+            Spans::from(spans)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines() {
+        let source = "a\r\nbc\n\nd";
+        assert_eq!(
+            line_ranges(source, 0..source.len()),
+            vec![0..1, 3..5, 6..6, 7..8]
+        );
+        assert_eq!(line_ranges(source, 3..6), vec![3..5]);
+        assert_eq!(line_ranges(source, 6..6), vec![6..6]);
+        let lines = line_ranges(source, 0..8);
+        assert_eq!(line_of_byte(&lines, 4), 1);
+        // `\r` and `\n` of a CRLF line break
+        assert_eq!(line_of_byte(&lines, 1), 0);
+        assert_eq!(line_of_byte(&lines, 2), 0);
+        assert_eq!(line_of_byte(&lines, 6), 2);
+        assert_eq!(line_of_byte(&lines, 8), 3);
+    }
+}
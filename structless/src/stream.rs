@@ -0,0 +1,176 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use tree_sitter::Point;
+
+/// Text read from a stream on a background thread, like stdin of a pipe
+#[derive(Debug)]
+pub struct Stream {
+    receiver: Receiver<io::Result<String>>,
+}
+
+impl Stream {
+    pub fn spawn<R: Read + Send + 'static>(reader: R) -> Stream {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || read_into(reader, &sender, false));
+        Stream { receiver }
+    }
+
+    /// Reads what is appended to the file at `path` after `offset`, like
+    /// `tail -f`. The stream never ends, it checks for more text every
+    /// `FOLLOW_INTERVAL` once it reached the end of the file.
+    pub fn follow(path: &Path, offset: u64) -> io::Result<Stream> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || read_into(file, &sender, true));
+        Ok(Stream { receiver })
+    }
+
+    /// All text that arrived since the last call, without waiting for more,
+    /// and whether the stream has ended
+    pub fn receive(&self) -> io::Result<(String, bool)> {
+        let mut text = String::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(chunk) => text.push_str(&chunk?),
+                Err(TryRecvError::Empty) => return Ok((text, false)),
+                Err(TryRecvError::Disconnected) => return Ok((text, true)),
+            }
+        }
+    }
+}
+
+/// How often a followed file is checked for appended text
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sends the text read from `reader` in chunks, until the end or, when
+/// `follow`ing, until the receiver is dropped
+fn read_into<R: Read>(mut reader: R, sender: &Sender<io::Result<String>>, follow: bool) {
+    let mut buffer = vec![0; 64 * 1024];
+    // bytes of a UTF-8 sequence that is split between two reads
+    let mut pending = Vec::new();
+    loop {
+        let result = match reader.read(&mut buffer) {
+            Ok(0) if follow => {
+                thread::sleep(FOLLOW_INTERVAL);
+                // sending nothing tells if the receiver is still there
+                Ok(String::new())
+            }
+            Ok(0) => break,
+            Ok(read) => {
+                pending.extend_from_slice(&buffer[..read]);
+                let complete = complete_prefix(&pending);
+                let text = String::from_utf8_lossy(&pending[..complete]).into_owned();
+                pending.drain(..complete);
+                Ok(text)
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => Err(error),
+        };
+        let failed = result.is_err();
+        if sender.send(result).is_err() || failed {
+            return;
+        }
+    }
+    if !pending.is_empty() {
+        let _ = sender.send(Ok(String::from_utf8_lossy(&pending).into_owned()));
+    }
+}
+
+/// Length of the longest prefix of `bytes` that does not end in the middle of
+/// a UTF-8 sequence
+fn complete_prefix(bytes: &[u8]) -> usize {
+    // a sequence is at most 4 bytes long, so only the last 3 bytes can start
+    // an incomplete one
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        let is_continuation = byte & 0b1100_0000 == 0b1000_0000;
+        if !is_continuation {
+            let length = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if length > back {
+                bytes.len() - back
+            } else {
+                bytes.len()
+            };
+        }
+    }
+    bytes.len()
+}
+
+/// Position after `text`, if it starts at `point`
+pub fn advance(point: Point, text: &str) -> Point {
+    match text.rfind('\n') {
+        Some(last) => Point::new(
+            point.row + text.matches('\n').count(),
+            text.len() - last - 1,
+        ),
+        None => Point::new(point.row, point.column + text.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_split_utf8_sequences() {
+        let bytes = "aä€".as_bytes();
+        assert_eq!(complete_prefix(bytes), bytes.len());
+        assert_eq!(complete_prefix(&bytes[..2]), 1);
+        assert_eq!(complete_prefix(&bytes[..4]), 3);
+        assert_eq!(complete_prefix(&bytes[..5]), 3);
+    }
+
+    #[test]
+    fn reads_everything() {
+        let stream = Stream::spawn(io::Cursor::new("line\n".repeat(20_000)));
+        let mut text = String::new();
+        loop {
+            let (chunk, ended) = stream.receive().unwrap();
+            text.push_str(&chunk);
+            if ended {
+                break;
+            }
+        }
+        assert_eq!(text.len(), 100_000);
+        assert_eq!(advance(Point::new(0, 0), &text), Point::new(20_000, 0));
+        assert_eq!(advance(Point::new(2, 3), "ab"), Point::new(2, 5));
+    }
+
+    #[test]
+    fn follows_appended_text() {
+        let path = std::env::temp_dir().join(format!("structless-follow-{}", std::process::id()));
+        std::fs::write(&path, "{}\n").unwrap();
+        let stream = Stream::follow(&path, 3).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        io::Write::write_all(&mut file, b"{\"a\": 1}\n").unwrap();
+
+        let mut text = String::new();
+        for _ in 0..50 {
+            let (chunk, ended) = stream.receive().unwrap();
+            assert!(!ended);
+            text.push_str(&chunk);
+            if !text.is_empty() {
+                break;
+            }
+            thread::sleep(FOLLOW_INTERVAL);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text, "{\"a\": 1}\n");
+    }
+}
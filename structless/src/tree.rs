@@ -1,264 +1,259 @@
+use std::ops::Range;
+
 use tui::{
-  buffer::Buffer,
-  layout::{Corner, Rect},
-  style::Style,
-  text::Text,
-  widgets::{Block, StatefulWidget, Widget},
+    buffer::Buffer,
+    layout::Rect,
+    style::Style,
+    text::Spans,
+    widgets::{Block, StatefulWidget, Widget},
 };
 use unicode_width::UnicodeWidthStr;
 
+/// Columns used per level of depth, by an indent guide or a fold marker
+pub const INDENT_WIDTH: usize = 2;
+
 #[derive(Debug, Clone, Default)]
-pub struct ListState {
-  offset: usize,
-  selected: Option<usize>,
+pub struct TreeState {
+    /// Index of the first visible item
+    offset: usize,
+    selected: Option<usize>,
 }
 
-impl ListState {
-  pub fn selected(&self) -> Option<usize> {
-      self.selected
-  }
+impl TreeState {
+    pub fn select(&mut self, index: Option<usize>) {
+        self.selected = index;
+        if index.is_none() {
+            self.offset = 0;
+        }
+    }
+
+    /// Index of the first visible item
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Scrolls to `offset`, as far as the selected item stays visible
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
 
-  pub fn select(&mut self, index: Option<usize>) {
-      self.selected = index;
-      if index.is_none() {
-          self.offset = 0;
-      }
-  }
+    /// Range of the `len` items to show in `max_height` rows, such that the
+    /// selected item is visible and the view scrolls as little as possible.
+    /// Only these items have to be passed to `Tree::new`.
+    pub fn get_items_bounds(&mut self, len: usize, max_height: usize) -> Range<usize> {
+        // don't leave rows empty when the list got shorter
+        let offset = self.offset.min(len.saturating_sub(max_height));
+        let start = match self.selected {
+            Some(selected) if selected < offset => selected,
+            Some(selected) if selected >= offset + max_height => selected + 1 - max_height,
+            _ => offset,
+        };
+        self.offset = start;
+        start..(start + max_height).min(len)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct ListItem<'a> {
-  content: Text<'a>,
-  style: Style,
+/// Whether a row can be folded, and if it currently is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldMarker {
+    /// Node without children
+    None,
+    Collapsed,
+    Expanded,
+    /// Closing row of an expanded node
+    End,
+}
+
+impl FoldMarker {
+    fn symbol(self) -> &'static str {
+        match self {
+            FoldMarker::None | FoldMarker::End => "  ",
+            FoldMarker::Collapsed => "▸ ",
+            FoldMarker::Expanded => "▾ ",
+        }
+    }
 }
 
-impl<'a> ListItem<'a> {
-  pub fn new<T>(content: T) -> ListItem<'a>
-  where
-      T: Into<Text<'a>>,
-  {
-      ListItem {
-          content: content.into(),
-          style: Style::default(),
-      }
-  }
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeItem<'a> {
+    content: Spans<'a>,
+    depth: usize,
+    marker: FoldMarker,
+    in_selected_subtree: bool,
+}
 
-  pub fn style(mut self, style: Style) -> ListItem<'a> {
-      self.style = style;
-      self
-  }
+impl<'a> TreeItem<'a> {
+    pub fn new<T>(content: T, depth: usize, marker: FoldMarker) -> TreeItem<'a>
+    where
+        T: Into<Spans<'a>>,
+    {
+        TreeItem {
+            content: content.into(),
+            depth,
+            marker,
+            in_selected_subtree: false,
+        }
+    }
 
-  pub fn height(&self) -> usize {
-      self.content.height()
-  }
+    /// Marks the item as part of the subtree of the selected node
+    pub fn in_selected_subtree(mut self, in_subtree: bool) -> TreeItem<'a> {
+        self.in_selected_subtree = in_subtree;
+        self
+    }
 }
 
-/// A widget to display several items among which one can be selected (optional)
-///
-/// # Examples
-///
-/// ```
-/// # use tui::widgets::{Block, Borders, List, ListItem};
-/// # use tui::style::{Style, Color, Modifier};
-/// let items = [ListItem::new("Item 1"), ListItem::new("Item 2"), ListItem::new("Item 3")];
-/// List::new(items)
-///     .block(Block::default().title("List").borders(Borders::ALL))
-///     .style(Style::default().fg(Color::White))
-///     .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
-///     .highlight_symbol(">>");
-/// ```
+/// A widget to display the flattened lines of a syntax tree, with indent
+/// guides and fold markers. Only the visible items are passed in, starting at
+/// the offset of the `TreeState`, see `TreeState::get_items_bounds`.
 #[derive(Debug, Clone)]
 pub struct Tree<'a> {
-  block: Option<Block<'a>>,
-  items: Vec<ListItem<'a>>,
-  /// Style used as a base style for the widget
-  style: Style,
-  start_corner: Corner,
-  /// Style used to render selected item
-  highlight_style: Style,
-  /// Symbol in front of the selected item (Shift all items to the right)
-  highlight_symbol: Option<&'a str>,
-  /// Whether to repeat the highlight symbol for each line of the selected item
-  repeat_highlight_symbol: bool,
+    block: Option<Block<'a>>,
+    items: Vec<TreeItem<'a>>,
+    /// Style used as a base style for the widget
+    style: Style,
+    /// Style used to render selected item
+    highlight_style: Style,
+    /// Style patched onto the items in the subtree of the selected node
+    subtree_style: Style,
+    /// Style of the indent guides and fold markers
+    guide_style: Style,
+    /// Symbol in front of the selected item (Shift all items to the right)
+    highlight_symbol: Option<&'a str>,
 }
 
 impl<'a> Tree<'a> {
-  pub fn new<T>(items: T) -> Tree<'a>
-  where
-      T: Into<Vec<ListItem<'a>>>,
-  {
-      Tree {
-          block: None,
-          style: Style::default(),
-          items: items.into(),
-          start_corner: Corner::TopLeft,
-          highlight_style: Style::default(),
-          highlight_symbol: None,
-          repeat_highlight_symbol: false,
-      }
-  }
-
-  pub fn block(mut self, block: Block<'a>) -> Tree<'a> {
-      self.block = Some(block);
-      self
-  }
-
-  pub fn style(mut self, style: Style) -> Tree<'a> {
-      self.style = style;
-      self
-  }
+    pub fn new<T>(items: T) -> Tree<'a>
+    where
+        T: Into<Vec<TreeItem<'a>>>,
+    {
+        Tree {
+            block: None,
+            style: Style::default(),
+            items: items.into(),
+            highlight_style: Style::default(),
+            subtree_style: Style::default(),
+            guide_style: Style::default(),
+            highlight_symbol: None,
+        }
+    }
 
-  pub fn highlight_symbol(mut self, highlight_symbol: &'a str) -> Tree<'a> {
-      self.highlight_symbol = Some(highlight_symbol);
-      self
-  }
+    pub fn block(mut self, block: Block<'a>) -> Tree<'a> {
+        self.block = Some(block);
+        self
+    }
 
-  pub fn highlight_style(mut self, style: Style) -> Tree<'a> {
-      self.highlight_style = style;
-      self
-  }
+    pub fn style(mut self, style: Style) -> Tree<'a> {
+        self.style = style;
+        self
+    }
 
-  pub fn repeat_highlight_symbol(mut self, repeat: bool) -> Tree<'a> {
-      self.repeat_highlight_symbol = repeat;
-      self
-  }
+    pub fn highlight_symbol(mut self, highlight_symbol: &'a str) -> Tree<'a> {
+        self.highlight_symbol = Some(highlight_symbol);
+        self
+    }
 
-  pub fn start_corner(mut self, corner: Corner) -> Tree<'a> {
-      self.start_corner = corner;
-      self
-  }
+    pub fn highlight_style(mut self, style: Style) -> Tree<'a> {
+        self.highlight_style = style;
+        self
+    }
 
-  fn get_items_bounds(
-      &self,
-      selected: Option<usize>,
-      offset: usize,
-      max_height: usize,
-  ) -> (usize, usize) {
-      let offset = offset.min(self.items.len().saturating_sub(1));
-      let mut start = offset;
-      let mut end = offset;
-      let mut height = 0;
-      for item in self.items.iter().skip(offset) {
-          if height + item.height() > max_height {
-              break;
-          }
-          height += item.height();
-          end += 1;
-      }
+    pub fn subtree_style(mut self, style: Style) -> Tree<'a> {
+        self.subtree_style = style;
+        self
+    }
 
-      let selected = selected.unwrap_or(0).min(self.items.len() - 1);
-      while selected >= end {
-          height = height.saturating_add(self.items[end].height());
-          end += 1;
-          while height > max_height {
-              height = height.saturating_sub(self.items[start].height());
-              start += 1;
-          }
-      }
-      while selected < start {
-          start -= 1;
-          height = height.saturating_add(self.items[start].height());
-          while height > max_height {
-              end -= 1;
-              height = height.saturating_sub(self.items[end].height());
-          }
-      }
-      (start, end)
-  }
+    pub fn guide_style(mut self, style: Style) -> Tree<'a> {
+        self.guide_style = style;
+        self
+    }
 }
 
 impl<'a> StatefulWidget for Tree<'a> {
-  type State = ListState;
+    type State = TreeState;
 
-  fn render(mut self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-      buf.set_style(area, self.style);
-      let list_area = match self.block.take() {
-          Some(b) => {
-              let inner_area = b.inner(area);
-              b.render(area, buf);
-              inner_area
-          }
-          None => area,
-      };
+    fn render(mut self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        buf.set_style(area, self.style);
+        let tree_area = match self.block.take() {
+            Some(b) => {
+                let inner_area = b.inner(area);
+                b.render(area, buf);
+                inner_area
+            }
+            None => area,
+        };
 
-      if list_area.width < 1 || list_area.height < 1 {
-          return;
-      }
+        if tree_area.width < 1 || tree_area.height < 1 || self.items.is_empty() {
+            return;
+        }
 
-      if self.items.is_empty() {
-          return;
-      }
-      let list_height = list_area.height as usize;
+        let highlight_symbol = self.highlight_symbol.unwrap_or("");
+        let blank_symbol = " ".repeat(highlight_symbol.width());
 
-      let (start, end) = self.get_items_bounds(state.selected, state.offset, list_height);
-      state.offset = start;
+        for (row, (i, item)) in self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(row, item)| (state.offset + row, item))
+            .take(tree_area.height as usize)
+            .enumerate()
+        {
+            let y = tree_area.top() + row as u16;
+            let area = Rect {
+                x: tree_area.left(),
+                y,
+                width: tree_area.width,
+                height: 1,
+            };
+            let mut item_style = self.style;
+            if item.in_selected_subtree {
+                item_style = item_style.patch(self.subtree_style);
+            }
+            buf.set_style(area, item_style);
 
-      let highlight_symbol = self.highlight_symbol.unwrap_or("");
-      let blank_symbol = " ".repeat(highlight_symbol.width());
+            let is_selected = state.selected == Some(i);
+            let symbol = if is_selected {
+                highlight_symbol
+            } else {
+                &blank_symbol
+            };
+            let max_width = tree_area.width as usize;
+            let (mut x, _) = buf.set_stringn(area.x, y, symbol, max_width, item_style);
 
-      let mut current_height = 0;
-      let has_selection = state.selected.is_some();
-      for (i, item) in self
-          .items
-          .iter_mut()
-          .enumerate()
-          .skip(state.offset)
-          .take(end - start)
-      {
-          let (x, y) = match self.start_corner {
-              Corner::BottomLeft => {
-                  current_height += item.height() as u16;
-                  (list_area.left(), list_area.bottom() - current_height)
-              }
-              _ => {
-                  let pos = (list_area.left(), list_area.top() + current_height);
-                  current_height += item.height() as u16;
-                  pos
-              }
-          };
-          let area = Rect {
-              x,
-              y,
-              width: list_area.width,
-              height: item.height() as u16,
-          };
-          let item_style = self.style.patch(item.style);
-          buf.set_style(area, item_style);
+            let guides = "│ ".repeat(item.depth) + item.marker.symbol();
+            let remaining = max_width.saturating_sub((x - area.x) as usize);
+            let guide_style = item_style.patch(self.guide_style);
+            x = buf.set_stringn(x, y, guides, remaining, guide_style).0;
 
-          let is_selected = state.selected.map(|s| s == i).unwrap_or(false);
-          for (j, line) in item.content.lines.iter().enumerate() {
-              // if the item is selected, we need to display the hightlight symbol:
-              // - either for the first line of the item only,
-              // - or for each line of the item if the appropriate option is set
-              let symbol = if is_selected && (j == 0 || self.repeat_highlight_symbol) {
-                  highlight_symbol
-              } else {
-                  &blank_symbol
-              };
-              let (elem_x, max_element_width) = if has_selection {
-                  let (elem_x, _) = buf.set_stringn(
-                      x,
-                      y + j as u16,
-                      symbol,
-                      list_area.width as usize,
-                      item_style,
-                  );
-                  (elem_x, (list_area.width - (elem_x - x)) as u16)
-              } else {
-                  (x, list_area.width)
-              };
-              buf.set_spans(elem_x, y + j as u16, line, max_element_width as u16);
-          }
-          if is_selected {
-              buf.set_style(area, self.highlight_style);
-          }
-      }
-  }
+            let remaining = max_width.saturating_sub((x - area.x) as usize);
+            buf.set_spans(x, y, &item.content, remaining as u16);
+            if is_selected {
+                buf.set_style(area, self.highlight_style);
+            }
+        }
+    }
 }
 
 impl<'a> Widget for Tree<'a> {
-  fn render(self, area: Rect, buf: &mut Buffer) {
-      let mut state = ListState::default();
-      StatefulWidget::render(self, area, buf, &mut state);
-  }
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let mut state = TreeState::default();
+        StatefulWidget::render(self, area, buf, &mut state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_follow_selection() {
+        let mut state = TreeState::default();
+        state.select(Some(2));
+        assert_eq!(state.get_items_bounds(10, 4), 0..4);
+        state.select(Some(6));
+        assert_eq!(state.get_items_bounds(10, 4), 3..7);
+        state.select(Some(1));
+        assert_eq!(state.get_items_bounds(10, 4), 1..5);
+        state.select(Some(9));
+        assert_eq!(state.get_items_bounds(10, 4), 6..10);
+    }
 }